@@ -2,7 +2,9 @@ use crate::rp_error::RPError;
 use crate::voters;
 use crate::poll;
 use crate::config;
+use crate::storage::Storage;
 use std::collections::HashMap;
+use std::path::Path;
 extern crate lettre;
 
 
@@ -41,11 +43,11 @@ pub struct Admin {
 }
 
 impl Admin {
-    pub fn new(voter: &str) -> Admin
+    pub fn new(storage: &dyn Storage, voter: &str) -> Admin
     {
         let mut adm = Admin { 
-                voters: voters::get_voter_list().unwrap_or(Vec::new()), 
-                polls: poll::get_poll_list().unwrap_or(Vec::new()),
+                voters: voters::get_voter_list(storage).unwrap_or(Vec::new()), 
+                polls: poll::get_poll_list(storage).unwrap_or(Vec::new()),
                 admin: voter.to_string(),
                 inv_name: HashMap::new(),
             };
@@ -56,13 +58,13 @@ impl Admin {
     }
 }
 
-pub fn get_admin(voter: &str) -> Admin {
-    return Admin::new(voter);
+pub fn get_admin(storage: &dyn Storage, voter: &str) -> Admin {
+    return Admin::new(storage, voter);
 }
 
-pub fn update_voter(actor: &str, action: &str, voter_name: &str, voter: Option<&voters::Voter>) -> Result<bool, RPError> {
+pub fn update_voter(storage: &dyn Storage, actor: &str, action: &str, voter_name: &str, voter: Option<&voters::Voter>) -> Result<bool, RPError> {
     // Check if the current user is admin too
-    let admin = get_admin(actor);
+    let admin = get_admin(storage, actor);
     let cur_user_is_admin = match admin.voters.iter().filter(|&x| x.username == actor).next() 
         {
             Some(v) => v.admin,
@@ -74,21 +76,22 @@ pub fn update_voter(actor: &str, action: &str, voter_name: &str, voter: Option<&
 
     match action.to_ascii_lowercase().as_str()
     {
-        "delete" => Ok(voters::delete_voter(&voter_name)),
+        "delete" => Ok(voters::delete_voter(storage, &voter_name)),
         "update" => {
             if voter.is_none() {
                 return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is empty", actor))));
             }
-            Ok(voters::update_voter(&voter_name, voter.unwrap()))
+            voter.unwrap().validate()?;
+            Ok(voters::update_voter(storage, &voter_name, voter.unwrap()))
         },
         _ => Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", action))))
     } 
 
 }
 
-pub fn update_poll(cfg: Option<&config::Config>, actor: &str, action: &str, poll_filename: &str, poll: Option<&poll::Poll>) -> Result<bool, RPError> {
+pub fn update_poll(storage: &dyn Storage, cfg: Option<&config::Config>, actor: &str, action: &str, poll_filename: &str, poll: Option<&poll::Poll>) -> Result<bool, RPError> {
     // Check if the current user is admin too
-    let admin = get_admin(actor);
+    let admin = get_admin(storage, actor);
     let cur_user_is_admin = match admin.voters.iter().filter(|&x| x.username == actor).next() 
         {
             Some(v) => v.admin,
@@ -100,15 +103,20 @@ pub fn update_poll(cfg: Option<&config::Config>, actor: &str, action: &str, poll
 
     match action.to_ascii_lowercase().as_str()
     {
-        "delete" => Ok(poll::delete_poll(&poll_filename)),
+        "delete" => Ok(poll::delete_poll(storage, &poll_filename)),
         "edit" => {
-            return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not connected", action))));
+            if poll.is_none() {
+                return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is empty", actor))));
+            }
+            poll::edit_poll(storage, &poll_filename, poll.unwrap())?;
+            Ok(true)
         },
         "update" => {
             if poll.is_none() {
                 return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is empty", actor))));
             }
-            Ok(poll::update_poll(&poll_filename, poll.unwrap()))
+            poll.unwrap().validate()?;
+            Ok(poll::update_poll(storage, &poll_filename, poll.unwrap()))
         },
         "del_voter" => {
             // Extract the poll to update first
@@ -116,7 +124,7 @@ pub fn update_poll(cfg: Option<&config::Config>, actor: &str, action: &str, poll
             if info.len() != 2 || !admin.inv_name.contains_key(info[1]) {
                 return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is empty", actor))));
             }
-            Ok(poll::del_voter_in_poll(info[0], info[1]))
+            Ok(poll::del_voter_in_poll(storage, info[0], info[1]))
         },
         "add_voter" => {
             // Extract the poll to update first
@@ -124,15 +132,15 @@ pub fn update_poll(cfg: Option<&config::Config>, actor: &str, action: &str, poll
             if info.len() != 2 || !admin.inv_name.contains_key(info[1]) {
                 return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is empty", actor))));
             }
-            Ok(poll::add_voter_in_poll(info[0], info[1]))
+            Ok(poll::add_voter_in_poll(storage, info[0], info[1]))
         },
         "sendemail" => {
             if cfg.is_none() || cfg.unwrap().smtp_server.is_none() {
                 return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("No configuration for mail sending"))));
             }
             // Collect all emails for each voter and send them an email if valid
-            let tokens = poll::gen_voters_token(poll_filename)?;
-            let poll_desc = poll::get_poll_desc(poll_filename, false)?;
+            let tokens = poll::gen_voters_token(storage, poll_filename)?;
+            let poll_desc = poll::get_poll_desc(storage, poll_filename, false)?;
             return send_emails(cfg.unwrap(), admin, tokens, &poll_desc, true);
         },
         "reminder" => {
@@ -140,8 +148,8 @@ pub fn update_poll(cfg: Option<&config::Config>, actor: &str, action: &str, poll
                 return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("No configuration for mail sending"))));
             }
             // Collect all emails for each voter and send them an email if valid
-            let tokens = poll::gen_voters_token(poll_filename)?;
-            let poll_desc = poll::get_poll_desc(poll_filename, false)?;
+            let tokens = poll::gen_voters_token(storage, poll_filename)?;
+            let poll_desc = poll::get_poll_desc(storage, poll_filename, false)?;
             return send_emails(cfg.unwrap(), admin, tokens, &poll_desc, false);
         },
         _ => Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", action))))
@@ -154,9 +162,10 @@ fn send_email_impl<'a>( admin: &Admin,
                         poll_desc: &poll::ParsedPoll, 
                         subject: &str, 
                         base_url: &str,
+                        templates_dir: &Path,
                         transport: &mut impl lettre::Transport::<'a>,
                         invitation: bool ) -> Result<bool, RPError> {
-    let tera = match tera::Tera::new("templates/*.smtp.tera") {
+    let tera = match tera::Tera::new(&templates_dir.join("*.smtp.tera").to_string_lossy()) {
         Ok(v) => v,
         Err(e) => { return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::Other, format!("Tera engine error: {}", e)))); }
     };
@@ -248,7 +257,7 @@ pub fn send_emails(cfg: &config::Config, admin: Admin, tokens: Vec<poll::Token>,
     // Either build a SMTP transport or use system's sendmail 
     if cfg.smtp_server.as_ref().unwrap() == "sendmail" {
         let mut transport = SendmailTransport::new();
-        return send_email_impl(&admin, &sender, &tokens, poll_desc, &subject, &base_url, &mut transport, invitation);
+        return send_email_impl(&admin, &sender, &tokens, poll_desc, &subject, &base_url, &cfg.templates_dir(), &mut transport, invitation);
     } else
     {
         let mut mailer = match SmtpClient::new_simple(cfg.smtp_server.as_ref().unwrap()) { //&format!("{}:{}", cfg.smtp_server.as_ref().unwrap(), match cfg.smtp_port { Some(v) => v, None => 25u16 })) {
@@ -266,7 +275,7 @@ pub fn send_emails(cfg: &config::Config, admin: Admin, tokens: Vec<poll::Token>,
             mailer = mailer.credentials(Credentials::new(cfg.smtp_username.as_ref().unwrap().clone(), cfg.smtp_password.as_ref().unwrap_or(&"".to_string()).clone()));
         }
         let mut transport = mailer.transport();
-        let res = send_email_impl(&admin, &sender, &tokens, poll_desc, &subject, &base_url, &mut transport, invitation);
+        let res = send_email_impl(&admin, &sender, &tokens, poll_desc, &subject, &base_url, &cfg.templates_dir(), &mut transport, invitation);
         transport.close();
         return res;
     };
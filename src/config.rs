@@ -1,6 +1,7 @@
 extern crate glob; 
 
 use std::fs;
+use std::path::PathBuf;
 use crate::rp_error::RPError;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -23,6 +24,38 @@ pub struct Config {
     pub smtp_invite_subject: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub smtp_reminder_subject: Option<String>,
+    // Where the polls and voters are stored
+    #[serde(default)]
+    pub storage: StorageKind,
+    // The SQLite database file (default to rangepoll.db), only used by the sqlite storage
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub database: Option<String>,
+    // Where the files are found, the relative paths below are relative to data_dir (default to the current folder)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub data_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub polls_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub voters_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub templates_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub static_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub secret_file: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Copy, Clone)]
+pub enum StorageKind {
+    // A YAML file per poll in polls/ and per voter in voters/
+    #[serde(rename = "yaml")]
+    Yaml,
+    // A SQLite database
+    #[serde(rename = "sqlite")]
+    Sqlite,
+}
+impl Default for StorageKind {
+    fn default() -> Self { StorageKind::Yaml }
 }
 
 impl Config {
@@ -40,12 +73,36 @@ impl Config {
                 smtp_sender: Some("no_reply@localhost".to_string()),
                 smtp_invite_subject: Some("Invitation for voting".to_string()),
                 smtp_reminder_subject: Some("Invitation for voting (reminder)".to_string()),
+                storage: StorageKind::Yaml,
+                database: None,
+                data_dir: None,
+                polls_dir: None,
+                voters_dir: None,
+                templates_dir: None,
+                static_dir: None,
+                secret_file: None,
             }
     }
 
+    pub fn data_dir(&self) -> PathBuf {
+        PathBuf::from(self.data_dir.as_ref().map_or(".", |x| x.as_str()))
+    }
+    // Resolve the given path in the data folder (an absolute path is kept as is)
+    fn in_data_dir(&self, path: &Option<String>, default: &str) -> PathBuf {
+        self.data_dir().join(path.as_ref().map_or(default, |x| x.as_str()))
+    }
+    pub fn polls_dir(&self) -> PathBuf { self.in_data_dir(&self.polls_dir, "polls") }
+    pub fn voters_dir(&self) -> PathBuf { self.in_data_dir(&self.voters_dir, "voters") }
+    pub fn templates_dir(&self) -> PathBuf { self.in_data_dir(&self.templates_dir, "templates") }
+    pub fn static_dir(&self) -> PathBuf { self.in_data_dir(&self.static_dir, "static") }
+    pub fn secret_file(&self) -> PathBuf { self.in_data_dir(&self.secret_file, "secret.txt") }
+    pub fn database(&self) -> PathBuf { self.in_data_dir(&self.database, "rangepoll.db") }
+
     pub fn dump(&self) -> String {
-        format!("baseURL: {}\ndisableLogin: {}\nenableAdmin: {}\nallowEditor: {}\nsmtp: {} with {},*** sender: {}\nMail subject: invite({}), remind({})",
-            self.base_url, self.disable_login, self.enable_admin, self.allow_editor, self.smtp_server.as_ref().unwrap_or(&"sendmail".to_string()), self.smtp_username.as_ref().unwrap_or(&"anonymous".to_string()), self.smtp_sender.as_ref().unwrap_or(&"no_reply@<yourhost>".to_string()), self.smtp_invite_subject.as_ref().unwrap_or(&"We need you!".to_string()), self.smtp_reminder_subject.as_ref().unwrap_or(&"We still need you!".to_string())
+        format!("baseURL: {}\ndisableLogin: {}\nenableAdmin: {}\nallowEditor: {}\nsmtp: {} with {},*** sender: {}\nMail subject: invite({}), remind({})\nstorage: {:?}{}\npolls: {}, voters: {}, templates: {}, static: {}, secret: {}",
+            self.base_url, self.disable_login, self.enable_admin, self.allow_editor, self.smtp_server.as_ref().unwrap_or(&"sendmail".to_string()), self.smtp_username.as_ref().unwrap_or(&"anonymous".to_string()), self.smtp_sender.as_ref().unwrap_or(&"no_reply@<yourhost>".to_string()), self.smtp_invite_subject.as_ref().unwrap_or(&"We need you!".to_string()), self.smtp_reminder_subject.as_ref().unwrap_or(&"We still need you!".to_string()),
+            self.storage, match self.storage { StorageKind::Sqlite => format!(" ({})", self.database().display()), _ => "".to_string() },
+            self.polls_dir().display(), self.voters_dir().display(), self.templates_dir().display(), self.static_dir().display(), self.secret_file().display()
         )
     }
 }
@@ -4,18 +4,18 @@
 #[macro_use] extern crate serde_derive;
 extern crate clap;
 
-use std::path::{ Path, PathBuf };
+use std::path::PathBuf;
 use std::collections::HashMap;
 use rocket::response::NamedFile;
 use rocket_contrib::templates::Template;
+use rocket_contrib::json::Json;
 use rocket::http::{ Cookies, Cookie, Status };
 use rocket::response::{ Flash, Redirect };
-use rocket::request::{ Form, LenientForm };
+use rocket::request::{ Form, FormDataError, LenientForm };
 use rocket::config::{ Config, Environment };
 use rocket::State;
 use rocket::response::status::Custom;
 use std::sync::Mutex;
-use std::fs;
 
 // Let authentication be checked with Request Guard
 use rocket::request::{ self, Request, FromRequest };
@@ -29,10 +29,13 @@ mod voters;
 mod config;
 mod rp_error;
 mod admin;
+mod storage;
+mod migration;
 
 struct GlobalConfig
 {
     config : Mutex<config::Config>,
+    storage : Box<dyn storage::Storage>,
 }
 
 #[derive(FromForm)]
@@ -60,11 +63,11 @@ impl<'a, 'r> FromRequest<'a, 'r> for Voter {
 }
 
 
-// Extract the "unknown" field name as vote only if they are integers
+// Extract the "unknown" field name as vote, they must be integers
 impl<'f> request::FromForm<'f> for poll::VotesForVoter {
-    type Error = ();
+    type Error = rp_error::BallotError;
 
-    fn from_form(form_items: &mut request::FormItems<'f>, _: bool) -> Result<Self, ()> {
+    fn from_form(form_items: &mut request::FormItems<'f>, _: bool) -> Result<Self, rp_error::BallotError> {
         let mut votes = poll::VotesForVoter {
             username: String::new(),
             votes: HashMap::new(),
@@ -76,10 +79,10 @@ impl<'f> request::FromForm<'f> for poll::VotesForVoter {
             }
             else {
                 match value.parse() {
-                    Ok(n) => votes.votes.insert(key, n),
-                    // Ignore invalid votes anyway
-                    Err(_) => None,
-                };
+                    Ok(n) => { votes.votes.insert(key, n); },
+                    // A tampered vote is rejected, it's not an abstention
+                    Err(_) => { return Err(rp_error::BallotError::InvalidValue { choice: key, value: value }); },
+                }
             }
         }
 
@@ -96,6 +99,7 @@ struct UpdateVoter {
     new_voter_fullname: String,
     new_voter_password: String,
     new_voter_admin: bool,
+    new_voter_weight: Option<f32>,
 }
 #[derive(FromForm)]
 struct UpdatePoll {
@@ -139,7 +143,7 @@ fn get_admin(voter: Voter, cfg: State<GlobalConfig>) -> Result< Template, Custom
         ctx.insert("msg", "Admin page disabled in configuration");
         return Err(Custom(Status::MethodNotAllowed, Template::render("error/421", ctx)));
     }
-    let admin = admin::get_admin(&voter.name);
+    let admin = admin::get_admin(&*cfg.storage, &voter.name);
     return Ok(Template::render("admin", admin));
 }
 #[get("/admin", rank=2)]
@@ -158,7 +162,7 @@ fn get_update_voter(voter: Voter, cfg: State<GlobalConfig>, action: String, file
         ctx.insert("msg", "Admin page disabled in configuration");
         return Err(Custom(Status::MethodNotAllowed, Template::render("error/421", ctx)));
     }
-    match admin::update_voter(&voter.name, &action, &filename, None)
+    match admin::update_voter(&*cfg.storage, &voter.name, &action, &filename, None)
     {
         Ok(_) => { return Ok(Redirect::to("/admin")); },
         Err(_e) =>
@@ -186,15 +190,22 @@ fn post_update_voter(voter: Voter, cfg: State<GlobalConfig>, new_voter: LenientF
         return Err(Custom(Status::MethodNotAllowed, Template::render("error/421", ctx)));
     }
     let v = voters::Voter {
+        format_version: migration::VOTER_FORMAT_VERSION,
         username: new_voter.new_voter_name.clone(),
         email: Some(new_voter.new_voter_email.clone()),
         presentation: new_voter.new_voter_presentation.clone(),
         fullname: Some(new_voter.new_voter_fullname.clone()),
         password: new_voter.new_voter_password.clone(),
         admin: new_voter.new_voter_admin,
+        weight: new_voter.new_voter_weight,
         filename: None,
     };
-    match admin::update_voter(&voter.name, "update", &new_voter.new_voter_filename, Some(&v))
+    if let Err(e) = v.validate() {
+        let mut ctx = HashMap::new();
+        ctx.insert("msg", format!("Invalid data, please check your inputs: {}", e));
+        return Err(Custom(Status::Unauthorized, Template::render("error/401", ctx)));
+    }
+    match admin::update_voter(&*cfg.storage, &voter.name, "update", &new_voter.new_voter_filename, Some(&v))
     {
         Ok(_) => { return Ok(Redirect::to("/admin")); },
         Err(_e) =>
@@ -221,7 +232,7 @@ fn get_update_poll(voter: Voter, cfg: State<GlobalConfig>, action: String, filen
     let cfgcfg = cfg.config.lock().unwrap();
     let config = Some(&*cfgcfg);
 
-    match admin::update_poll(config, &voter.name, &action, &filename, None)
+    match admin::update_poll(&*cfg.storage, config, &voter.name, &action, &filename, None)
     {
         Ok(_) => { return Ok(Redirect::to("/admin")); },
         Err(_e) =>
@@ -255,7 +266,7 @@ fn post_update_poll(voter: Voter, cfg: State<GlobalConfig>, new_poll: LenientFor
     
     let v = poll::Poll::new(new_poll.new_poll_name.clone(), None, None);
 
-    match admin::update_poll(None, &voter.name, "update", &new_poll.new_poll_filename, Some(&v))
+    match admin::update_poll(&*cfg.storage, None, &voter.name, "update", &new_poll.new_poll_filename, Some(&v))
     {
         Ok(_) => { return Ok(Redirect::to("/admin")); },
         Err(_e) =>
@@ -279,7 +290,7 @@ fn get_edit_poll(voter: Voter, cfg: State<GlobalConfig>, pollname: String) -> Re
         ctx.insert("msg", "Admin page disabled in configuration");
         return Err(Custom(Status::MethodNotAllowed, Template::render("error/421", ctx)));
     }
-    let admin = admin::get_admin(&voter.name);
+    let admin = admin::get_admin(&*cfg.storage, &voter.name);
     let cur_user_is_admin = match admin.voters.iter().filter(|&x| x.username == voter.name).next() 
         {
             Some(v) => v.admin,
@@ -291,19 +302,22 @@ fn get_edit_poll(voter: Voter, cfg: State<GlobalConfig>, pollname: String) -> Re
         return Err(Custom(Status::MethodNotAllowed, Template::render("error/421", ctx)));
     }
 
-    let mut poll = match poll::find_poll_desc(&pollname) {
+    let mut poll = match poll::find_poll_desc(&*cfg.storage, &pollname) {
         Ok(v) => v,
         Err(_) => { return Ok(Redirect::to("/admin")); }
     };
 
     // Fix any markdown we could have in the poll to include the markdown content itself
     if poll.desc_markdown.is_some() {
-        poll.description = Some(fs::read_to_string(format!("polls/{}", poll.desc_markdown.as_ref().unwrap())).unwrap_or_default().clone());
+        poll.description = Some(cfg.storage.read_markdown(poll.desc_markdown.as_ref().unwrap()).unwrap_or_default());
     }
     for mut choice in poll.choices.iter_mut() {
         if choice.desc_markdown.is_some() {
-            choice.description = Some(fs::read_to_string(format!("polls/{}", choice.desc_markdown.as_ref().unwrap())).unwrap_or_default().clone());
+            choice.description = Some(cfg.storage.read_markdown(choice.desc_markdown.as_ref().unwrap()).unwrap_or_default());
         }
+        // The ballots can't be edited, the stored ones are kept when saving
+        choice.vote.clear();
+        choice.voter.clear();
     }
 
     // We need to convert the poll to JSON first since the editor only accept JSON
@@ -334,34 +348,39 @@ fn post_edit_poll(voter: Voter, cfg: State<GlobalConfig>, new_poll: LenientForm<
             return Err(Custom(Status::Unauthorized, Template::render("error/401", ctx)));
         }
     };
+    if let Err(e) = poll.validate() {
+        let mut ctx = HashMap::new();
+        ctx.insert("msg", format!("Invalid data, please check your inputs: {}", e));
+        return Err(Custom(Status::Unauthorized, Template::render("error/401", ctx)));
+    }
 
     // Need to fix any markdown found if any here since it can't be saved in YAML 
     if poll.desc_markdown.is_some() {
-        let md_file = format!("polls/{}.md", &new_poll.new_poll_filename);
-        if fs::write(&md_file, poll.desc_markdown.as_ref().unwrap()).is_err() {
+        let md_file = format!("{}.md", &new_poll.new_poll_filename);
+        if cfg.storage.save_markdown(&md_file, poll.desc_markdown.as_ref().unwrap()).is_err() {
             let mut ctx = HashMap::new();
             ctx.insert("msg", "Writing poll failed");
             return Err(Custom(Status::MethodNotAllowed, Template::render("error/421", ctx)));
         }
         // Ok, remember the file path here
-        poll.desc_markdown = Some(format!("{}.md", &new_poll.new_poll_filename));
+        poll.desc_markdown = Some(md_file);
     }
     // Do the same for choices markdown if found
     for mut choice in poll.choices.iter_mut() {
         if choice.desc_markdown.is_some() {
-            let md_file = format!("polls/{}_{}.md", &new_poll.new_poll_filename, &choice.name);
-            if fs::write(&md_file, choice.desc_markdown.as_ref().unwrap()).is_err() {
+            let md_file = format!("{}_{}.md", &new_poll.new_poll_filename, &choice.name);
+            if cfg.storage.save_markdown(&md_file, choice.desc_markdown.as_ref().unwrap()).is_err() {
                 let mut ctx = HashMap::new();
                 ctx.insert("msg", "Writing poll choice failed");
                 return Err(Custom(Status::MethodNotAllowed, Template::render("error/421", ctx)));
             }
             // Ok, remember the file path here
-            choice.desc_markdown = Some(format!("{}_{}.md", &new_poll.new_poll_filename, &choice.name));
+            choice.desc_markdown = Some(md_file);
         }
     }
 
-    // Ok, should be able to save the poll now
-    match admin::update_poll(None, &voter.name, "update", &new_poll.new_poll_filename, Some(&poll))
+    // Ok, should be able to save the poll now, the changes are merged in the stored poll so the ballots cast meanwhile are kept
+    match admin::update_poll(&*cfg.storage, None, &voter.name, "edit", &new_poll.new_poll_filename, Some(&poll))
     {
         Ok(_) => { return Ok(Redirect::to("/admin")); },
         Err(_e) =>
@@ -382,9 +401,9 @@ fn get_edit_poll_not_logged(_param: PathBuf) -> Custom<Template> {
 
 
 #[get("/token/<token>")]
-fn log_with_token(mut cookies: Cookies, token: String) -> Result< Redirect, Custom<Template> > {
+fn log_with_token(mut cookies: Cookies, cfg: State<GlobalConfig>, token: String) -> Result< Redirect, Custom<Template> > {
     // Using JWT token here for authentication 
-    let voter = match poll::validate_token(&token) {
+    let voter = match poll::validate_token(&*cfg.storage, &token) {
         Ok(v) => v,
         Err(e) => {
             println!("Error ({}) with token: {}", e, token);
@@ -411,8 +430,8 @@ fn login(cfg: State<GlobalConfig>) -> Result<Template, Custom<Template> > {
 }
 
 #[post("/login", data = "<user>")]
-fn post_login(mut cookies: Cookies, user: LenientForm<User>) -> Result< Redirect, Custom<Template> > {
-    let voters = match voters::get_voter_list() {
+fn post_login(mut cookies: Cookies, cfg: State<GlobalConfig>, user: LenientForm<User>) -> Result< Redirect, Custom<Template> > {
+    let voters = match voters::get_voter_list(&*cfg.storage) {
         Ok(v) => v,
         Err(_) => Vec::new(),
     };
@@ -460,8 +479,9 @@ fn admin_panel_redirect() -> Redirect {
 
 
 #[get("/")]
-fn index() -> Option<NamedFile> {
-    NamedFile::open(Path::new("static/html/index.html")).ok()
+fn index(cfg: State<GlobalConfig>) -> Option<NamedFile> {
+    let static_dir = cfg.config.lock().unwrap().static_dir();
+    NamedFile::open(static_dir.join("html/index.html")).ok()
 }
 
 #[catch(404)]
@@ -473,10 +493,10 @@ fn not_found(req: &Request) -> Template {
 
 // Asynchronous javascript methods here
 #[get("/poll_list", rank=1)]
-fn poll_list(voter: Voter) -> Result<Template, Flash<Redirect>> {
+fn poll_list(voter: Voter, cfg: State<GlobalConfig>) -> Result<Template, Flash<Redirect>> {
     let mut map = HashMap::new();
     // Need to extract all available polls
-    let polls = match poll::get_poll_desc_list(&voter.name) {
+    let polls = match poll::get_poll_desc_list(&*cfg.storage, &voter.name) {
         Ok(v) => v,
         Err(_) => { map.insert("polls", vec![]); return Ok(Template::render("poll_list", &map)); },
     };
@@ -489,9 +509,9 @@ fn poll_list_not_logged() -> Flash<Redirect> {
 }
 
 #[get("/vote_for/<poll>", rank=1)]
-fn vote_for(poll: String, voter: Voter) -> Result<Template, Flash<Redirect>> {
+fn vote_for(poll: String, voter: Voter, cfg: State<GlobalConfig>) -> Result<Template, Flash<Redirect>> {
     // Need to extract all available polls
-    let mut ppoll = match poll::get_poll_desc(&poll, true) {
+    let mut ppoll = match poll::get_poll_desc(&*cfg.storage, &poll, true) {
         Ok(v) => v,
         Err(_) => { 
             let mut ctx = HashMap::new();
@@ -508,12 +528,18 @@ fn vote_for(poll: String, voter: Voter) -> Result<Template, Flash<Redirect>> {
     Ok(Template::render("vote_for", &ppoll))
 }
 #[post("/vote_for/<poll>", rank=1, data="<form>")]
-fn post_vote_for(poll: String, voter: Voter, form: Form<poll::VotesForVoter>) -> Result<Template, Flash<Redirect>> {
+fn post_vote_for(poll: String, voter: Voter, cfg: State<GlobalConfig>, form: Result<Form<poll::VotesForVoter>, FormDataError<'_, rp_error::BallotError>>) -> Result<Template, Flash<Redirect>> {
+    // An invalid vote is reported like the other ballot errors
+    let form = match form {
+        Ok(v) => v,
+        Err(FormDataError::Parse(e, _)) => { return Err(Flash::error(Redirect::to(format!("/not_allowed/{}/{}", "/poll_list", poll)), format!("{}", e))); },
+        Err(_) => { return Err(Flash::error(Redirect::to(format!("/not_allowed/{}/{}", "/poll_list", poll)), "Invalid form data")); },
+    };
     // Don't trust the form submitter and only use the authentication token we have generated here for the voter's name.
     let vote = poll::VotesForVoter { username: voter.name.clone(), votes: form.votes.clone() };
-    let mut ppoll = match poll::vote_for_poll(&poll, &vote) {
+    let mut ppoll = match poll::vote_for_poll(&*cfg.storage, &poll, &vote) {
         Ok(v) => v,
-        Err(e) => { return Err(Flash::error(Redirect::to(format!("/not_allowed/{}/{}", "/poll_list", poll)), format!("{:?}", e))); },
+        Err(e) => { return Err(Flash::error(Redirect::to(format!("/not_allowed/{}/{}", "/poll_list", poll)), format!("{}", e))); },
     };
 
     ppoll.user = voter.name.clone();
@@ -533,9 +559,9 @@ fn not_allowed(dest: String, from: String) -> Template {
 }
 
 #[get("/vote_results/<dest>", rank=1)]
-fn vote_results(dest: String, voter: Voter) -> Result<Template, Flash<Redirect>> {
+fn vote_results(dest: String, voter: Voter, cfg: State<GlobalConfig>) -> Result<Template, Flash<Redirect>> {
     // Need to extract vote results for the given name
-    let mut pollr = match poll::get_poll_result(dest.as_str(), voter.name.clone()) {
+    let mut pollr = match poll::get_poll_result(&*cfg.storage, dest.as_str(), voter.name.clone()) {
         Ok(v) => v,
         Err(e) => { return Err(Flash::error(Redirect::to(format!("/not_allowed/{}/{}", "/poll_list", dest)), format!("{:?}", e))); },
     };
@@ -548,6 +574,44 @@ fn vote_results_not_logged(_dest: String) -> Flash<Redirect> {
     Flash::error(Redirect::to("/login"), "Invalid credentials")
 }
 
+#[get("/api/vote_results/<dest>", rank=1)]
+fn api_vote_results(dest: String, voter: Voter, cfg: State<GlobalConfig>) -> Result<Json<poll::PollResult>, Status> {
+    match poll::get_poll_result(&*cfg.storage, dest.as_str(), voter.name.clone()) {
+        Ok(mut v) => { v.user = voter.name.clone(); Ok(Json(v)) },
+        Err(_) => Err(Status::Forbidden),
+    }
+}
+#[get("/api/vote_results/<_dest>", rank=2)]
+fn api_vote_results_not_logged(_dest: String) -> Status {
+    Status::Unauthorized
+}
+
+#[get("/compare_results/<dest>", rank=1)]
+fn compare_results(dest: String, voter: Voter, cfg: State<GlobalConfig>) -> Result<Template, Flash<Redirect>> {
+    let mut comparison = match poll::get_poll_comparison(&*cfg.storage, dest.as_str(), voter.name.clone()) {
+        Ok(v) => v,
+        Err(e) => { return Err(Flash::error(Redirect::to(format!("/not_allowed/{}/{}", "/poll_list", dest)), format!("{:?}", e))); },
+    };
+
+    comparison.user = voter.name.clone();
+    Ok(Template::render("compare_result", &comparison))
+}
+#[get("/compare_results/<_dest>", rank=2)]
+fn compare_results_not_logged(_dest: String) -> Flash<Redirect> {
+    Flash::error(Redirect::to("/login"), "Invalid credentials")
+}
+#[get("/api/compare_results/<dest>", rank=1)]
+fn api_compare_results(dest: String, voter: Voter, cfg: State<GlobalConfig>) -> Result<Json<poll::PollComparison>, Status> {
+    match poll::get_poll_comparison(&*cfg.storage, dest.as_str(), voter.name.clone()) {
+        Ok(v) => Ok(Json(v)),
+        Err(_) => Err(Status::Forbidden),
+    }
+}
+#[get("/api/compare_results/<_dest>", rank=2)]
+fn api_compare_results_not_logged(_dest: String) -> Status {
+    Status::Unauthorized
+}
+
 #[get("/menu", rank=1)]
 fn menu(voter: Voter) -> Template {
     let mut ctx = HashMap::new();
@@ -562,8 +626,9 @@ fn menu_not_logged() -> Flash<Redirect> {
 
 // Temporary stuff below
 #[get("/public/<file..>")]
-fn static_files(file: PathBuf) -> Option<NamedFile> {
-    NamedFile::open(Path::new("static/").join(file)).ok()
+fn static_files(file: PathBuf, cfg: State<GlobalConfig>) -> Option<NamedFile> {
+    let static_dir = cfg.config.lock().unwrap().static_dir();
+    NamedFile::open(static_dir.join(file)).ok()
 }
 
 
@@ -572,7 +637,7 @@ fn main() {
     let mut port;
     let mut host;
     let scheme;
-    let cfg = GlobalConfig { config: Mutex::new(config::Config::new()) };
+    let mut cfg = GlobalConfig { config: Mutex::new(config::Config::new()), storage: Box::new(storage::YamlStorage::new(&config::Config::new())) };
     let mut had_base_url = false;
 
     let cmd_args = App::new("rangepoll")
@@ -589,7 +654,15 @@ fn main() {
                         .arg(Arg::with_name("poll").short("g").long("gen-template").value_name("FILE").help("Generate a template poll YAML file and save to template.yaml (recommanded: polls/example.yml)").takes_value(true))
                         .arg(Arg::with_name("voter").short("v").long("gen-voter").value_name("FILE").help("Generate a template voter YAML file and save to voter.yaml (recommanded: voters/voter.yml)").takes_value(true))
                         .arg(Arg::with_name("token").short("t").long("gen-token").value_name("poll name").help("Generate tokens for the given poll's voters so it can be distributed by email for example").takes_value(true))
+                        .arg(Arg::with_name("import").short("i").long("import").help("Import the YAML polls and voters (from polls/ and voters/) in the storage selected in the configuration"))
+                        .arg(Arg::with_name("migrate").long("migrate").help("Upgrade the polls and voters of the storage selected in the configuration to the current format (the original YAML files are kept as .bak files)"))
                         .arg(Arg::with_name("config").short("c").long("config").value_name("FILE").help("Specify the configuration file to use").default_value("config.yml").takes_value(true))
+                        .arg(Arg::with_name("data_dir").long("data-dir").value_name("DIR").env("RANGEPOLL_DATA_DIR").help("The folder containing the data, the other paths are relative to it (default to the current folder)").takes_value(true))
+                        .arg(Arg::with_name("polls_dir").long("polls-dir").value_name("DIR").env("RANGEPOLL_POLLS_DIR").help("The folder containing the polls (default to polls)").takes_value(true))
+                        .arg(Arg::with_name("voters_dir").long("voters-dir").value_name("DIR").env("RANGEPOLL_VOTERS_DIR").help("The folder containing the voters (default to voters)").takes_value(true))
+                        .arg(Arg::with_name("templates_dir").long("templates-dir").value_name("DIR").env("RANGEPOLL_TEMPLATES_DIR").help("The folder containing the templates (default to templates)").takes_value(true))
+                        .arg(Arg::with_name("static_dir").long("static-dir").value_name("DIR").env("RANGEPOLL_STATIC_DIR").help("The folder containing the static files (default to static)").takes_value(true))
+                        .arg(Arg::with_name("secret_file").long("secret-file").value_name("FILE").env("RANGEPOLL_SECRET_FILE").help("The file containing the tokens' secret (default to secret.txt)").takes_value(true))
                         .get_matches();
     
     // Deal with optional config path
@@ -603,6 +676,15 @@ fn main() {
                 return; 
             }
         };
+        // The command line (or environment) overrides the configured paths
+        let dirs: &mut config::Config = &mut config;
+        let paths = vec![ ("data_dir", &mut dirs.data_dir), ("polls_dir", &mut dirs.polls_dir), ("voters_dir", &mut dirs.voters_dir),
+                          ("templates_dir", &mut dirs.templates_dir), ("static_dir", &mut dirs.static_dir), ("secret_file", &mut dirs.secret_file) ];
+        for (arg, path) in paths {
+            if let Some(o) = cmd_args.value_of(arg) {
+                *path = Some(o.to_string());
+            }
+        }
         let host_url = match Url::parse(config.base_url.as_str()) {
             Ok(u) => { had_base_url = true; u },
             Err(e) => { eprintln!("Invalid base URL in config: {}", e); return; }
@@ -613,6 +695,28 @@ fn main() {
         scheme = host_url.scheme().to_string();
     }
 
+    if cmd_args.is_present("migrate") {
+        let count = migration::migrate_storage(&cfg.config.lock().unwrap());
+        println!("Migrated {} poll(s) and voter(s)", count);
+        return;
+    }
+
+    // Open the storage selected in the configuration
+    {
+        let storage = match storage::open(&cfg.config.lock().unwrap()) {
+            Ok(s) => s,
+            Err(e) => { eprintln!("Error while opening storage: {}", e); return; }
+        };
+        cfg.storage = storage;
+    }
+    if cmd_args.is_present("import") {
+        match storage::copy_storage(&storage::YamlStorage::new(&cfg.config.lock().unwrap()), &*cfg.storage) {
+            Ok(_) => println!("Imported the YAML polls and voters"),
+            Err(e) => eprintln!("Error while importing: {}", e),
+        }
+        return;
+    }
+
     if let Some(o) = cmd_args.value_of("port") {
         port = o.parse().unwrap_or(8000);
     }
@@ -633,12 +737,12 @@ fn main() {
         return;
     }
     if let Some(o) = cmd_args.value_of("voter") {
-        voters::gen_template(o);
+        voters::gen_template(&*cfg.storage, o);
         println!("Generated template voter file to {:?}", o);
         return;
     }
     if let Some(o) = cmd_args.value_of("token") {
-        let tokens = match poll::gen_voters_token(o) {
+        let tokens = match poll::gen_voters_token(&*cfg.storage, o) {
             Ok(v) => v,
             Err(e) => { eprintln!("Error: {}", e); return; }
         };
@@ -656,9 +760,11 @@ fn main() {
 
     println!("Configuration used:\n{}", cfg.config.lock().unwrap().dump());
 
+    let templates_dir = cfg.config.lock().unwrap().templates_dir();
     let config = Config::build(Environment::Staging)
                         .address(host_interface.to_string())
                         .port(port)
+                        .extra("template_dir", templates_dir.to_string_lossy().to_string())
                         .finalize().expect("Error building webserver config");
 
     let r = rocket::custom(config);
@@ -673,11 +779,11 @@ fn main() {
      // Login or logout
      .mount("/", routes![login, post_login, logout, not_allowed, log_with_token])
      // Asynchronous application
-     .mount("/", routes![poll_list, vote_for, post_vote_for, vote_results, menu, 
+     .mount("/", routes![poll_list, vote_for, post_vote_for, vote_results, api_vote_results, compare_results, api_compare_results, menu, 
                          get_user_menu, get_admin, get_update_voter, post_update_voter,
                          get_update_poll, post_update_poll, get_edit_poll, post_edit_poll])
      // Not logged in async routes
-     .mount("/", routes![poll_list_not_logged, vote_for_not_logged, vote_results_not_logged, menu_not_logged, 
+     .mount("/", routes![poll_list_not_logged, vote_for_not_logged, vote_results_not_logged, api_vote_results_not_logged, compare_results_not_logged, api_compare_results_not_logged, menu_not_logged, 
                          get_user_menu_not_logged, get_admin_not_logged, get_update_voter_not_logged,
                          get_update_poll_not_logged, get_edit_poll_not_logged])
 
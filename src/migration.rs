@@ -0,0 +1,162 @@
+use std::fs;
+use std::path::Path;
+use chrono::Utc;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_yaml::{ Mapping, Value };
+use crate::rp_error::RPError;
+use crate::poll::Poll;
+use crate::voters::Voter;
+use crate::storage;
+use crate::config;
+
+// A migration upgrades a file from a version to the next one, the n-th migration upgrades from version n
+// When the format of the poll or voter files changes, add the migration from the previous version here
+type Migration = fn(&mut Mapping);
+
+const POLL_MIGRATIONS: [Migration; 1] = [ poll_v0_to_v1 ];
+const VOTER_MIGRATIONS: [Migration; 1] = [ voter_v0_to_v1 ];
+
+// The current version of the poll and voter files
+pub const POLL_FORMAT_VERSION: u32 = POLL_MIGRATIONS.len() as u32;
+pub const VOTER_FORMAT_VERSION: u32 = VOTER_MIGRATIONS.len() as u32;
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_string())
+}
+
+// Version 0 is the format before the format_version key was added
+fn poll_v0_to_v1(poll: &mut Mapping) {
+    // The file stem was saved in the file, but it's always taken from the file's path
+    poll.remove(&key("filename"));
+    // A "desc" key was ignored, use it as the description if there's none
+    if let Some(desc) = poll.remove(&key("desc")) {
+        if !poll.contains_key(&key("description")) && !poll.contains_key(&key("desc_markdown")) {
+            poll.insert(key("description"), desc);
+        }
+    }
+    // The options' keys are kebab-case, the snake_case keys were ignored
+    if let Some(Value::Mapping(options)) = poll.get_mut(&key("options")) {
+        let snake_keys: Vec<String> = options.iter().filter_map(|(k, _)| k.as_str()).filter(|k| k.contains('_')).map(|k| k.to_string()).collect();
+        for snake_key in snake_keys {
+            let kebab_key = key(&snake_key.replace('_', "-"));
+            let value = options.remove(&key(&snake_key)).unwrap();
+            if !options.contains_key(&kebab_key) {
+                options.insert(kebab_key, value);
+            }
+        }
+    }
+    // The choices skipped by a voter had no entry, they are now recorded as abstentions (a null vote) so the poll can be complete
+    if let Some(Value::Sequence(choices)) = poll.get_mut(&key("choices")) {
+        let mut voters: Vec<Value> = Vec::new();
+        for choice in choices.iter() {
+            if let Some(Value::Sequence(names)) = choice.get("voter") {
+                for name in names {
+                    if !voters.contains(name) {
+                        voters.push(name.clone());
+                    }
+                }
+            }
+        }
+        for choice in choices.iter_mut().filter_map(|c| c.as_mapping_mut()) {
+            let mut names = match choice.remove(&key("voter")) { Some(Value::Sequence(v)) => v, _ => Vec::new() };
+            let mut votes = match choice.remove(&key("vote")) { Some(Value::Sequence(v)) => v, _ => Vec::new() };
+            votes.resize(names.len(), Value::Null);
+            for voter in &voters {
+                if !names.contains(voter) {
+                    names.push(voter.clone());
+                    votes.push(Value::Null);
+                }
+            }
+            choice.insert(key("vote"), Value::Sequence(votes));
+            choice.insert(key("voter"), Value::Sequence(names));
+        }
+    }
+}
+
+fn voter_v0_to_v1(voter: &mut Mapping) {
+    // Same as for the polls, the file stem is taken from the file's path
+    voter.remove(&key("filename"));
+}
+
+// The version of a poll or voter file's content (0 if it can't be read)
+pub fn format_version(content: &str) -> u32 {
+    serde_yaml::from_str::<Value>(content).ok().and_then(|x| x.get("format_version").and_then(|v| v.as_u64())).unwrap_or(0) as u32
+}
+
+// Upgrade the file's content to the current version, and return the version it had
+fn migrate(value: &mut Value, migrations: &[Migration], what: &str) -> Result<u32, RPError> {
+    let map = match value.as_mapping_mut() {
+        Some(v) => v,
+        None => { return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid {} file", what)))); }
+    };
+    let version = map.get(&key("format_version")).and_then(|x| x.as_u64()).unwrap_or(0) as usize;
+    if version > migrations.len() {
+        return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("The {} file's format version {} is newer than this server's version {}", what, version, migrations.len()))));
+    }
+    for migration in &migrations[version..] {
+        migration(map);
+    }
+    map.insert(key("format_version"), Value::Number(migrations.len().into()));
+    Ok(version as u32)
+}
+
+pub fn migrate_poll(value: &mut Value) -> Result<u32, RPError> {
+    migrate(value, &POLL_MIGRATIONS, "poll")
+}
+
+pub fn migrate_voter(value: &mut Value) -> Result<u32, RPError> {
+    migrate(value, &VOTER_MIGRATIONS, "voter")
+}
+
+// Serialize a poll or a voter for the storage, in the current version and without the file stem
+pub fn to_yaml<T: Serialize>(item: &T, version: u32) -> Result<String, serde_yaml::Error> {
+    let mut value = serde_yaml::to_value(item)?;
+    if let Some(map) = value.as_mapping_mut() {
+        map.remove(&key("filename"));
+        map.insert(key("format_version"), Value::Number(version.into()));
+    }
+    serde_yaml::to_string(&value)
+}
+
+// Rewrite the file in the current version if it's older, the original file is kept as <name>.yml.v<version>.<date>.bak
+fn migrate_file<T: Serialize + DeserializeOwned>(path: &Path, migrate: fn(&mut Value) -> Result<u32, RPError>, version: u32) -> Result<bool, RPError> {
+    let mut value: Value = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+    let previous = migrate(&mut value)?;
+    if previous == version {
+        return Ok(false);
+    }
+    // Check the migrated file can be read before replacing it
+    let item: T = serde_yaml::from_value(value)?;
+    fs::copy(path, path.with_extension(format!("yml.v{}.{}.bak", previous, Utc::now().format("%Y%m%d%H%M%S"))))?;
+    storage::write_file_atomic(path, &to_yaml(&item, version)?)?;
+    Ok(true)
+}
+
+fn migrate_folder<T: Serialize + DeserializeOwned>(dir: &Path, migrate: fn(&mut Value) -> Result<u32, RPError>, version: u32) -> usize {
+    let mut count = 0;
+    for entry in storage::yaml_files(dir) {
+        let path = match entry {
+            Ok(v) => v,
+            Err(e) => { println!("Failed with error: {:?}", e); continue; }
+        };
+        match migrate_file::<T>(&path, migrate, version) {
+            Ok(true) => { println!("Migrated: {:?}", path.display()); count += 1; },
+            Ok(false) => {},
+            Err(e) => println!("Failed migrating {:?} with error {}", path.display(), e),
+        }
+    }
+    count
+}
+
+// Upgrade all the polls and voters of the storage selected in the configuration, return the number of migrated polls and voters
+pub fn migrate_storage(cfg: &config::Config) -> usize {
+    match cfg.storage {
+        config::StorageKind::Yaml => migrate_folder::<Poll>(&cfg.polls_dir(), migrate_poll, POLL_FORMAT_VERSION) + migrate_folder::<Voter>(&cfg.voters_dir(), migrate_voter, VOTER_FORMAT_VERSION),
+        // The database is upgraded in a transaction, there's no backup file
+        config::StorageKind::Sqlite => match storage::SqliteStorage::open(&cfg.database(), cfg.secret_file()).and_then(|db| db.migrate()) {
+            Ok(v) => v,
+            Err(e) => { println!("Failed migrating {:?} with error {}", cfg.database().display(), e); 0 },
+        },
+    }
+}
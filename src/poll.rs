@@ -1,10 +1,8 @@
 
 extern crate chrono;
-extern crate glob; 
 
 use comrak::{markdown_to_html, ComrakOptions};
 
-use glob::glob;
 use chrono::{DateTime, Utc, Timelike};
 use std::fs;
 use std::path::Path;
@@ -12,11 +10,14 @@ use std::collections::{ HashMap, HashSet };
 use array2d::Array2D;
 use std::iter::FromIterator;
 use jsonwebtoken::{ encode, Algorithm, Header, EncodingKey, decode, DecodingKey, Validation };
-use crate::rp_error::RPError;
+use crate::rp_error::{ RPError, BallotError };
+use crate::voters;
+use crate::storage::Storage;
+use crate::migration;
 
 pub const DEADLINE_FORMAT: &'static str = "%Y-%m-%d";
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Choice {
     pub name: String,
     #[serde(skip)]
@@ -26,9 +27,9 @@ pub struct Choice {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub desc_markdown: Option<String>,
     #[serde(default)]
-    vote: Vec<usize>,
+    pub vote: Vec<Option<i32>>,     // None (~ in the file) if the voter abstained on this choice
     #[serde(default)]
-    voter: Vec<String>,
+    pub voter: Vec<String>,
 }
 
 // This is the parsed choice from a file
@@ -36,14 +37,14 @@ pub struct Choice {
 pub struct ParsedChoice {
     pub name: String,
     pub desc: String,
-    pub vote: Vec<usize>,
+    pub vote: Vec<Option<i32>>,
     pub voter: Vec<String>,
 }
 impl ParsedChoice {
-    fn new(choice: Choice, path: &Path, skip_summary: bool) -> Result<ParsedChoice, RPError> {
+    fn new(storage: &dyn Storage, choice: Choice, path: &Path, skip_summary: bool) -> Result<ParsedChoice, RPError> {
         Ok(ParsedChoice { 
             name: choice.name.clone(),
-            desc: build_desc(&choice.description, &choice.desc_markdown, path, skip_summary)?,
+            desc: build_desc(storage, &choice.description, &choice.desc_markdown, path, skip_summary)?,
             vote: choice.vote.clone(),
             voter: choice.voter.clone(),
         })
@@ -59,9 +60,21 @@ pub enum VotingAlgorithm {
     // Used for simple Yes/No polling, the choice with the highest number of Yes wins 
     #[serde(rename = "binary")]
     Binary,
-    // This is similar to max vote, that is the choice with the maximum total number of points wins but choice can't have the same number of point (obviously limited to 5 choices maximum)
+    // Each voter approves some choices (within the poll's limits), the choice approved by the largest share of the voters wins
+    #[serde(rename = "approval")]
+    Approval,
+    // This is similar to max vote, that is the choice with the maximum total number of points wins but the votes are first replaced by the rank of the choice for the voter (best choice gets N points, worst gets 1)
     #[serde(rename = "bordat")]
     Bordat,
+    // Classic Borda count: the best choice of a voter gets N-1 points, the next N-2 and so on down to 0
+    #[serde(rename = "borda")]
+    Borda,
+    // Dowdall (Nauru) Borda count: the choice ranked k-th by a voter gets 1/k point
+    #[serde(rename = "dowdall")]
+    Dowdall,
+    // Modified Borda count: a voter ranking only K choices gives K points to the best one, K-1 to the next, and so on, so partial ballots give fewer points
+    #[serde(rename = "modified-borda")]
+    ModifiedBorda,
     // This is similar to mean consensus vote, that is each choice is compared to each other choice individually and the last winner wins the vote           
     #[serde(rename = "condorcet")]
     Condorcet,
@@ -74,6 +87,52 @@ pub enum VotingAlgorithm {
     // In this mode, the choice with the lowest acceptance is eliminated and the other vote with a lower value are transfered to the other choice, repeat until only one remains
     #[serde(rename = "successive-elimination")]
     SuccessiveElimination,        
+    // This is a Condorcet method where the choices are ranked by the strength of the strongest path of pairwise victories between them
+    #[serde(rename = "schulze")]
+    Schulze,
+    // This is a Condorcet method where the pairwise victories are locked from the largest margin to the smallest, unless they create a cycle
+    #[serde(rename = "ranked-pairs")]
+    RankedPairs,
+    // Each choice gets the median of its grades, and the choice with the best median wins (ties are broken by removing the median grades until they differ)
+    #[serde(rename = "majority-judgment")]
+    MajorityJudgment,
+    // Score then automatic runoff: the 2 choices with the maximum total number of points are kept, and the one preferred by most voters wins
+    #[serde(rename = "star")]
+    Star,
+    // Multiple winners: choices reaching the Droop quota are elected and their surplus is transfered to the next choices, else the last choice is eliminated
+    #[serde(rename = "stv")]
+    SingleTransferableVote,
+    // Multiple winners: the most approved choice is elected, then the ballots approving elected choices are weighted down, repeat until all seats are filled
+    #[serde(rename = "spav")]
+    SequentialProportionalApproval,
+}
+
+impl VotingAlgorithm {
+    // All the implemented algorithms
+    pub fn all() -> Vec<VotingAlgorithm> {
+        vec![VotingAlgorithm::Max, VotingAlgorithm::Binary, VotingAlgorithm::Approval, VotingAlgorithm::Bordat, VotingAlgorithm::Borda,
+             VotingAlgorithm::Dowdall, VotingAlgorithm::ModifiedBorda, VotingAlgorithm::Condorcet, VotingAlgorithm::FirstChoice,
+             VotingAlgorithm::FrenchSystem, VotingAlgorithm::SuccessiveElimination, VotingAlgorithm::Schulze, VotingAlgorithm::RankedPairs,
+             VotingAlgorithm::MajorityJudgment, VotingAlgorithm::Star, VotingAlgorithm::SingleTransferableVote, VotingAlgorithm::SequentialProportionalApproval]
+    }
+
+    // Some algorithms rank the choices for each voter so they don't accept the same vote for two choices
+    pub fn allow_same_vote(&self) -> bool {
+        match *self {
+            VotingAlgorithm::Max | VotingAlgorithm::Binary | VotingAlgorithm::Approval | VotingAlgorithm::Bordat | VotingAlgorithm::Borda
+            | VotingAlgorithm::Dowdall | VotingAlgorithm::ModifiedBorda | VotingAlgorithm::Condorcet | VotingAlgorithm::Schulze | VotingAlgorithm::RankedPairs 
+            | VotingAlgorithm::MajorityJudgment | VotingAlgorithm::Star | VotingAlgorithm::SequentialProportionalApproval => true,
+            _ => false,
+        }
+    }
+
+    // Those algorithms use a Yes/No ballot instead of the score ballot
+    pub fn binary_ballot(&self) -> bool {
+        match *self {
+            VotingAlgorithm::Binary | VotingAlgorithm::Approval | VotingAlgorithm::SequentialProportionalApproval => true,
+            _ => false,
+        }
+    }
 }
 
 impl Default for VotingAlgorithm {
@@ -83,10 +142,77 @@ impl Default for VotingAlgorithm {
 #[derive(Debug)]
 pub struct VotesForVoter {
     pub username: String,
-    pub votes: HashMap<String, u32>,
+    pub votes: HashMap<String, i32>,
+}
+
+// The range of the votes for a choice
+#[derive(Debug, PartialEq, Serialize, Deserialize, Copy, Clone)]
+pub struct ScoreRange {
+    pub min: i32,
+    pub max: i32,
+}
+
+// The most possible votes in a score range, so that the ballot form and the tallies stay small
+pub const MAX_SCORE_SPAN: i64 = 100;
+
+impl Default for ScoreRange {
+    fn default() -> Self { ScoreRange { min: 1, max: 5 } }
+}
+
+impl ScoreRange {
+    // The number of possible votes
+    pub fn span(&self) -> usize {
+        std::cmp::max(self.max as i64 - self.min as i64 + 1, 1) as usize
+    }
+    pub fn contains(&self, vote: i32) -> bool {
+        vote >= self.min && vote <= self.max
+    }
+    // Convert a vote to points so that algorithms don't depend on the scale: the lowest vote gives 1 point, the highest gives span() points
+    // (0 point is used for missing votes)
+    pub fn points(&self, vote: i32) -> usize {
+        // Clamp the vote in the range first (an inverted range is rejected when parsing, but don't underflow anyway)
+        let vote = std::cmp::max(std::cmp::min(vote, self.max), self.min) as i64;
+        (std::cmp::max(vote - self.min as i64, 0) + 1) as usize
+    }
+    // Convert points back to a vote on this scale
+    pub fn vote(&self, points: f32) -> f32 {
+        points + self.min as f32 - 1f32
+    }
+}
+
+// The conditions for a result to be valid, a poll without quorum always declares a winner
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Copy, Clone)]
+pub struct Quorum {
+    // The minimum number of allowed participants who voted
+    #[serde(rename = "min-voters", skip_serializing_if = "Option::is_none", default)]
+    pub min_voters:  Option<usize>,
+    // The minimum percentage of allowed participants who voted
+    #[serde(rename = "min-percent", skip_serializing_if = "Option::is_none", default)]
+    pub min_percent:  Option<f32>,
+    // The minimum mean vote of a winner (in the score range, or the share of Yes from 0 to 1 for Yes/No ballots)
+    #[serde(rename = "min-score", skip_serializing_if = "Option::is_none", default)]
+    pub min_score:  Option<f32>,
+}
+
+impl Quorum {
+    // Why the participation is too low, if it is
+    fn participation(&self, voted: usize, allowed: usize) -> Option<String> {
+        if let Some(min) = self.min_voters {
+            if voted < min {
+                return Some(format!("Quorum not reached: {} voter(s) out of the {} required", voted, min));
+            }
+        }
+        if let Some(min) = self.min_percent {
+            let percent = if allowed == 0 { 0f32 } else { voted as f32 * 100f32 / allowed as f32 };
+            if percent < min {
+                return Some(format!("Quorum not reached: {:.0}% of the participants voted, {}% required", percent, min));
+            }
+        }
+        None
+    }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone)]
 pub struct PollOptions {
     // Allow to skip a choice (in a vote)
     #[serde(rename = "allow-missing-choice", default)]
@@ -100,12 +226,80 @@ pub struct PollOptions {
     // Show the vote matrix in the result
     #[serde(rename = "show-vote-matrix", default)]
     pub show_vote_matrix:  bool,
+    // Show the pairwise preferences matrix in the result
+    #[serde(rename = "show-pairwise-matrix", default)]
+    pub show_pairwise_matrix:  bool,
+    // The number of choices to elect (only used by multiple winners algorithms, default to 1)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub seats:  Option<usize>,
+    // The range of the votes (default to 1 to 5 stars, ignored for Yes/No ballots)
+    #[serde(rename = "score-range", skip_serializing_if = "Option::is_none", default)]
+    pub score_range:  Option<ScoreRange>,
+    // How the abstentions (the choices skipped by a voter) are counted
+    #[serde(default)]
+    pub abstention:  AbstentionPolicy,
+    // How the tied choices are ordered
+    #[serde(rename = "tie-break", default)]
+    pub tie_break:  TieBreak,
+    // The seed of the lottery, recorded in the poll file with the first vote so the result doesn't change
+    #[serde(rename = "tie-break-seed", skip_serializing_if = "Option::is_none", default)]
+    pub tie_break_seed:  Option<u64>,
+    // The algorithm used to order the tied choices for the fallback tie break (default to max)
+    #[serde(rename = "tie-break-algorithm", skip_serializing_if = "Option::is_none", default)]
+    pub tie_break_algorithm:  Option<VotingAlgorithm>,
+    // The maximum number of approved choices (Yes votes) in a ballot, only used by Yes/No ballots
+    #[serde(rename = "max-approvals", skip_serializing_if = "Option::is_none", default)]
+    pub max_approvals:  Option<usize>,
+    // The minimum number of approved choices (Yes votes) in a ballot, only used by Yes/No ballots
+    #[serde(rename = "min-approvals", skip_serializing_if = "Option::is_none", default)]
+    pub min_approvals:  Option<usize>,
+    // The participation and the winner's score required to declare a winner
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub quorum:  Option<Quorum>,
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize, Copy, Clone)]
+pub enum TieBreak {
+    // The first declared choice in the poll wins
+    #[serde(rename = "declared-order")]
+    DeclaredOrder,
+    // A random order, from the recorded seed
+    #[serde(rename = "lottery")]
+    Lottery,
+    // The order given by another voting algorithm
+    #[serde(rename = "fallback")]
+    Fallback,
+    // No tie break, the result is a tie (the declared order is only used for display)
+    #[serde(rename = "tie")]
+    Tie,
+}
+impl Default for TieBreak {
+    fn default() -> Self { TieBreak::DeclaredOrder }
+}
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Copy, Clone)]
+pub enum AbstentionPolicy {
+    // The abstention isn't counted at all, as if the voter didn't see the choice
+    #[serde(rename = "ignore")]
+    Ignore,
+    // The abstention counts as the lowest possible vote
+    #[serde(rename = "lowest")]
+    Lowest,
+    // The abstention counts as the average vote of the other voters for this choice
+    #[serde(rename = "average")]
+    Average,
+}
+impl Default for AbstentionPolicy {
+    fn default() -> Self { AbstentionPolicy::Ignore }
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Poll {
-    name: String,
+    // The version of the file's format, older files are upgraded when loaded (see migration.rs)
+    #[serde(default)]
+    pub format_version: u32,
+    pub name: String,
 
     #[serde(skip)]
     filepath: String,
@@ -122,10 +316,10 @@ pub struct Poll {
     // Else, the markdown key is used as a file path that's read and then converted to HTML
     #[serde(skip_serializing_if = "Option::is_none")]
     pub desc_markdown: Option<String>,
-    allowed_participant: Vec<String>,
+    pub allowed_participant: Vec<String>,
     
     #[serde(with = "date_serde")]
-    deadline_date: DateTime<Utc>,
+    pub deadline_date: DateTime<Utc>,
     pub choices: Vec<Choice>,
 
     // Any of Bordat / Condorcet / etc. (see VotingAlgorithm)
@@ -139,6 +333,7 @@ pub struct Poll {
 impl Poll {
     pub fn new(name: String, desc: Option<String>, choices: Option<Vec<Choice>> ) -> Poll {
         Poll {   
+            format_version: migration::POLL_FORMAT_VERSION,
             name:name,
             filepath: "".to_string(),
             filename: None,
@@ -152,6 +347,128 @@ impl Poll {
             options: None,
         }
     }
+
+    // The poll's name in the storage (its file stem)
+    pub fn filestem(&self) -> String {
+        match &self.filename {
+            Some(v) => v.clone(),
+            None => Path::new(&self.filepath).file_stem().map_or("".to_string(), |x| x.to_str().unwrap().to_string()),
+        }
+    }
+
+    // Apply the changes made in the poll editor to this (freshly loaded) poll
+    // The ballots (matched by the choice name) and the lottery seed are kept, so the votes cast while editing aren't lost
+    fn apply_edit(&mut self, edited: &Poll) {
+        let mut choices = edited.choices.clone();
+        for choice in &mut choices {
+            match self.choices.iter().find(|x| x.name == choice.name) {
+                Some(stored) => { choice.vote = stored.vote.clone(); choice.voter = stored.voter.clone(); },
+                None => { choice.vote.clear(); choice.voter.clear(); },
+            }
+        }
+        let seed = self.options.as_ref().and_then(|o| o.tie_break_seed);
+        self.name = edited.name.clone();
+        self.description = edited.description.clone();
+        self.desc_markdown = edited.desc_markdown.clone();
+        self.allowed_participant = edited.allowed_participant.clone();
+        self.deadline_date = edited.deadline_date;
+        self.voting_algorithm = edited.voting_algorithm;
+        self.options = edited.options.clone();
+        if let (Some(opt), Some(seed)) = (self.options.as_mut(), seed) {
+            opt.tie_break_seed = Some(seed);
+        }
+        self.choices = choices;
+    }
+
+    // If every allowed participant has voted (abstentions included)
+    pub fn is_complete(&self) -> bool {
+        self.choices.iter().all(|choice| self.allowed_participant.iter().all(|voter| choice.voter.contains(voter)))
+    }
+
+    // The choices names, in the declared order
+    fn choice_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for choice in &self.choices {
+            if !names.contains(&choice.name) {
+                names.push(choice.name.clone());
+            }
+        }
+        names
+    }
+
+    // Record a seed for the lottery tie break if there's none yet
+    pub fn record_tie_break_seed(&mut self) {
+        if let Some(opt) = self.options.as_mut() {
+            if opt.tie_break == TieBreak::Lottery && opt.tie_break_seed.is_none() {
+                opt.tie_break_seed = Some(Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64);
+            }
+        }
+    }
+
+    // The choices, ordered by priority when breaking ties
+    fn tie_break_order(&self, voter_weights: &HashMap<String, f32>) -> Vec<String> {
+        let mut order = self.choice_names();
+        let opt = match self.options.as_ref() { Some(o) => o, None => return order };
+        match opt.tie_break {
+            TieBreak::DeclaredOrder | TieBreak::Tie => {},
+            TieBreak::Lottery => {
+                // Without a recorded seed (no vote yet or a file edited by hand), the poll name gives a stable one
+                let seed = opt.tie_break_seed.unwrap_or_else(|| self.name.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3)));
+                let mut rng = XorShift::new(seed);
+                // Fisher-Yates shuffle
+                for i in (1..order.len()).rev() {
+                    order.swap(i, rng.below(i + 1));
+                }
+            },
+            TieBreak::Fallback => {
+                let mut fallback = self.clone();
+                // The fallback algorithm's own ties are broken by the declared order
+                if let Some(o) = fallback.options.as_mut() {
+                    o.tie_break = TieBreak::DeclaredOrder;
+                    o.show_only_complete_result = false;
+                }
+                let result = PollResult::new(&fallback, opt.tie_break_algorithm.unwrap_or(VotingAlgorithm::Max), voter_weights);
+                // The choices missing in the fallback result (if it failed) keep the declared order after the others
+                order.sort_by_key(|name| result.votes.iter().position(|x| x == name).unwrap_or(usize::max_value()));
+            },
+        }
+        order
+    }
+
+    // The range of the votes for this poll, Yes/No ballots are 0 (for No) and 1 (for Yes)
+    pub fn score_range(&self) -> ScoreRange {
+        if self.voting_algorithm.binary_ballot() {
+            return ScoreRange { min: 0, max: 1 };
+        }
+        self.options.as_ref().and_then(|o| o.score_range).unwrap_or_default()
+    }
+
+    // Check the options are consistent, before using or saving the poll
+    pub fn validate(&self) -> Result<(), RPError> {
+        if let Some(range) = self.options.as_ref().and_then(|o| o.score_range) {
+            if range.min > range.max {
+                return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid score range: min ({}) is greater than max ({})", range.min, range.max))));
+            }
+            if range.max as i64 - range.min as i64 + 1 > MAX_SCORE_SPAN {
+                return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid score range: {} to {} allows more than {} votes", range.min, range.max, MAX_SCORE_SPAN))));
+            }
+        }
+        if let Some(opt) = self.options.as_ref() {
+            let invalid = |msg: String| Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, msg)));
+            if opt.seats == Some(0) {
+                return invalid("Invalid seats: at least one choice must be elected".to_string());
+            }
+            if let Some(max) = opt.max_approvals {
+                if max == 0 || max > self.choices.len() {
+                    return invalid(format!("Invalid max-approvals ({}): it must be between 1 and the number of choices ({})", max, self.choices.len()));
+                }
+                if let Some(min) = opt.min_approvals.filter(|&min| min > max) {
+                    return invalid(format!("Invalid approvals: min-approvals ({}) is greater than max-approvals ({})", min, max));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 // This is for the parsed poll from a file
@@ -166,15 +483,20 @@ pub struct ParsedPoll {
     pub deadline_near: bool,
     pub algorithm: VotingAlgorithm,
     pub missing_choice: bool,
+    pub same_vote: bool,
+    pub binary_ballot: bool,
+    pub score_values: Vec<i32>, // The possible votes, from the highest to the lowest
+    pub max_approvals: Option<usize>,
+    pub min_approvals: Option<usize>,
     pub choices: Vec<ParsedChoice>,
     pub user: String,
 }
 
 impl ParsedPoll {
-    fn new(poll: &Poll, skip_summary: bool) -> ParsedPoll {
+    fn new(storage: &dyn Storage, poll: &Poll, skip_summary: bool) -> ParsedPoll {
         ParsedPoll { 
             name: poll.name.clone(), 
-            desc: if skip_summary { build_desc(&poll.description, &poll.desc_markdown, &Path::new(&poll.filepath), true).unwrap_or(poll.desc.clone()) }
+            desc: if skip_summary { build_desc(storage, &poll.description, &poll.desc_markdown, &Path::new(&poll.filepath), true).unwrap_or(poll.desc.clone()) }
                   else { poll.desc.clone() }, 
             filepath: poll.filepath.clone(), 
             filename: Path::new(&poll.filepath).file_stem().unwrap().to_str().unwrap().to_string(), 
@@ -183,6 +505,11 @@ impl ParsedPoll {
             deadline_near: false,
             algorithm: poll.voting_algorithm,
             missing_choice: match &poll.options { Some(v) => v.allow_missing_choice, None => false },
+            same_vote: poll.voting_algorithm.allow_same_vote(),
+            binary_ballot: poll.voting_algorithm.binary_ballot(),
+            score_values: (poll.score_range().min..=poll.score_range().max).rev().collect(),
+            max_approvals: if poll.voting_algorithm.binary_ballot() { poll.options.as_ref().and_then(|o| o.max_approvals) } else { None },
+            min_approvals: if poll.voting_algorithm.binary_ballot() { poll.options.as_ref().and_then(|o| o.min_approvals) } else { None },
             choices: vec![],
             user: "".to_string(),
         }
@@ -205,9 +532,213 @@ pub struct PollDesc {
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct VoteMatrix
 {
-    voters:  Vec<String>,       // Built from a hash set so they are unique
-    choices: Vec<String>,       // Build from the initial choice list (that's the name of the choice)
-    matrix:  Vec<Vec<usize>>,   // The vote matrix itself
+    voters:  Vec<String>,       // The allowed participants first, in the declared order, then the previous participants
+    choices: Vec<String>,       // In the declared order (that's the name of the choice)
+    voted:   Vec<bool>,         // If the participant has voted
+    matrix:  Vec<Vec<Option<i32>>>, // The vote matrix itself (None if the voter didn't vote for the choice)
+}
+
+impl VoteMatrix {
+    // The matrix is displayed in the declared order of the choices and the participants, the participants who didn't vote yet get an empty row
+    fn new(poll: &Poll, voters: &Vec<String>, choices: &Vec<String>, raw_matrix: &Array2D<Option<i32>>) -> VoteMatrix {
+        let declared_choices = poll.choice_names();
+        let mut participants = poll.allowed_participant.clone();
+        participants.extend(voters.iter().filter(|x| !poll.allowed_participant.contains(x)).cloned());
+        let matrix = participants.iter().map(|voter| {
+            let row = voters.iter().position(|x| x == voter);
+            declared_choices.iter().map(|choice| {
+                let col = choices.iter().position(|x| x == choice).unwrap();
+                row.and_then(|row| raw_matrix[(row, col)])
+            }).collect()
+        }).collect();
+        VoteMatrix { voted: participants.iter().map(|x| voters.contains(x)).collect(), voters: participants, choices: declared_choices, matrix: matrix }
+    }
+}
+
+// The number of voters preferring each choice to each other choice, with the Condorcet winner and loser if any
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PairwiseMatrix
+{
+    choices: Vec<String>,                   // In the declared order
+    matrix:  Vec<Vec<f32>>,                 // matrix[a][b] is the number of voters preferring a to b (weighted)
+    condorcet_winner: Option<String>,       // The choice preferred to every other choice by a majority
+    condorcet_loser:  Option<String>,       // The choice every other choice is preferred to by a majority
+    cycle: bool,                            // If the majority preferences contain a cycle (A beats B beats C beats A)
+}
+
+impl PairwiseMatrix {
+    fn new(poll: &Poll, choices: &Vec<String>, vote_matrix: &Array2D<usize>, weights: &Vec<f32>) -> PairwiseMatrix {
+        let preferences = pairwise_preferences(vote_matrix, weights);
+        let declared_choices = poll.choice_names();
+        let index: Vec<usize> = declared_choices.iter().map(|x| choices.iter().position(|c| c == x).unwrap()).collect();
+        let matrix: Vec<Vec<f32>> = index.iter().map(|&i| index.iter().map(|&j| preferences[i][j]).collect()).collect();
+        let count = matrix.len();
+        let beats: Vec<Vec<bool>> = (0..count).map(|i| (0..count).map(|j| matrix[i][j] > matrix[j][i]).collect()).collect();
+        let winner = (0..count).find(|&i| (0..count).all(|j| i == j || beats[i][j]));
+        let loser = (0..count).find(|&i| (0..count).all(|j| i == j || beats[j][i]));
+        PairwiseMatrix {
+            cycle: (0..count).any(|i| reachable_choices(&beats, i)[i]),
+            condorcet_winner: winner.map(|i| declared_choices[i].clone()),
+            condorcet_loser: if count > 1 { loser.map(|i| declared_choices[i].clone()) } else { None },
+            choices: declared_choices,
+            matrix: matrix,
+        }
+    }
+}
+
+// A counting round, for the algorithms working in multiple rounds
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct VoteRound
+{
+    choices: Vec<String>,       // The choices still competing in this round
+    score:   Vec<f32>,          // The number of ballots for each choice in this round
+    no_preference: f32,         // The number of ballots that don't prefer any of the choices above (weighted)
+    eliminated: Vec<String>,    // The choices dropped at the end of this round
+    elected: Vec<String>,       // The choices elected at the end of this round (for multiple winners algorithms)
+}
+
+// A choice x choice matrix (row: choice, col: other choice)
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PreferenceMatrix
+{
+    choices: Vec<String>,
+    matrix:  Vec<Vec<f32>>,
+}
+
+impl PreferenceMatrix {
+    // The matrix is computed in the tie break order of the choices, but displayed in their declared order
+    fn new(poll: &Poll, choices: &Vec<String>, matrix: &Vec<Vec<f32>>) -> PreferenceMatrix {
+        let declared_choices = poll.choice_names();
+        let index: Vec<usize> = declared_choices.iter().map(|x| choices.iter().position(|c| c == x).unwrap()).collect();
+        PreferenceMatrix {
+            matrix: index.iter().map(|&i| index.iter().map(|&j| matrix[i][j]).collect()).collect(),
+            choices: declared_choices,
+        }
+    }
+}
+
+// A pairwise victory, in the order it was processed by the Ranked Pairs algorithm (the pairs with the same margin and support are in the declared order)
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct RankedPair
+{
+    winner: String,
+    loser:  String,
+    margin: f32,                // The number of voters preferring the winner minus the number of voters preferring the loser (weighted)
+    locked: bool,               // If false, the pair was skipped since it would have created a cycle
+}
+
+// The grades distribution of a choice, for the Majority Judgment algorithm
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeritProfile
+{
+    choice: String,
+    median: i32,
+    total:  f32,                // The number of grades given to this choice (weighted)
+    grades: Vec<MeritGrade>,    // The number of voters who gave each grade, starting from the lowest grade
+}
+
+// A grade in a merit profile
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeritGrade
+{
+    grade:  i32,
+    count:  f32,                // The number of voters who gave this grade (weighted)
+    hue:    usize,              // The color of the grade, from red (0) for the lowest to green (120) for the highest
+}
+
+// The distribution of the votes for a choice (abstentions are not counted)
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChoiceStats
+{
+    choice: String,
+    count: usize,               // The number of votes
+    abstentions: usize,
+    mean: f32,
+    median: f32,
+    std_dev: f32,
+    min: Option<i32>,
+    max: Option<i32>,
+    histogram: Vec<VoteCount>,  // The number of votes for each possible vote, from the lowest vote
+    polarization: f32,          // The variance of the votes divided by the highest possible variance (half the votes at each extreme), from 0 to 1
+    extremes: f32,              // The share of the votes with the lowest or the highest vote
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct VoteCount
+{
+    vote: i32,
+    count: usize,
+}
+
+impl ChoiceStats {
+    fn new(choice: &String, votes: &Vec<Option<i32>>, range: &ScoreRange) -> ChoiceStats {
+        let mut sorted: Vec<i32> = votes.iter().filter_map(|&x| x).collect();
+        sorted.sort();
+        let count = sorted.len();
+        let mean = if count == 0 { 0f32 } else { sorted.iter().sum::<i32>() as f32 / count as f32 };
+        let median = match count {
+            0 => 0f32,
+            n if n % 2 == 1 => sorted[n / 2] as f32,
+            n => (sorted[n / 2 - 1] + sorted[n / 2]) as f32 / 2f32,
+        };
+        let variance = if count == 0 { 0f32 } else { sorted.iter().map(|&x| (x as f32 - mean).powi(2)).sum::<f32>() / count as f32 };
+        let max_variance = ((range.max as f32 - range.min as f32) / 2f32).powi(2);
+        ChoiceStats {
+            choice: choice.clone(),
+            count: count,
+            abstentions: votes.len() - count,
+            mean: mean,
+            median: median,
+            std_dev: variance.sqrt(),
+            min: sorted.first().cloned(),
+            max: sorted.last().cloned(),
+            histogram: (range.min..=range.max).map(|vote| VoteCount { vote: vote, count: sorted.iter().filter(|&&x| x == vote).count() }).collect(),
+            polarization: if max_variance > 0f32 { variance / max_variance } else { 0f32 },
+            extremes: if count == 0 { 0f32 } else { sorted.iter().filter(|&&x| x == range.min || x == range.max).count() as f32 / count as f32 },
+        }
+    }
+}
+
+// How much the voters agree with each other
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Consensus
+{
+    agreement: Option<f32>,     // The mean Kendall tau between the rankings of each pair of voters, from -1 (opposite rankings) to 1 (same rankings)
+    polarization: f32,          // The mean polarization of the choices
+    divisive_winner: bool,      // If the winner's votes are polarized
+}
+
+// A choice whose polarization is above this is divisive
+const DIVISIVE_POLARIZATION: f32 = 0.5;
+
+impl Consensus {
+    fn new(raw_matrix: &Array2D<Option<i32>>, stats: &Vec<ChoiceStats>, winner: Option<&String>) -> Consensus {
+        // Kendall tau-a for each pair of voters, over the pairs of choices both voters voted for
+        let rows = raw_matrix.as_rows();
+        let mut taus = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            for other in rows.iter().skip(i + 1) {
+                let voted: Vec<(i32, i32)> = row.iter().zip(other.iter()).filter_map(|(a, b)| a.and_then(|a| b.map(|b| (a, b)))).collect();
+                let mut balance = 0;
+                let mut pairs = 0;
+                for (j, a) in voted.iter().enumerate() {
+                    for b in voted.iter().skip(j + 1) {
+                        balance += (a.0 - b.0).signum() * (a.1 - b.1).signum();
+                        pairs += 1;
+                    }
+                }
+                if pairs > 0 {
+                    taus.push(balance as f32 / pairs as f32);
+                }
+            }
+        }
+        let counted: Vec<&ChoiceStats> = stats.iter().filter(|x| x.count > 0).collect();
+        Consensus {
+            agreement: if taus.is_empty() { None } else { Some(taus.iter().sum::<f32>() / taus.len() as f32) },
+            polarization: if counted.is_empty() { 0f32 } else { counted.iter().map(|x| x.polarization).sum::<f32>() / counted.len() as f32 },
+            divisive_winner: stats.iter().any(|x| Some(&x.choice) == winner && x.polarization > DIVISIVE_POLARIZATION),
+        }
+    }
 }
 
 // This is the poll result
@@ -219,116 +750,235 @@ pub struct PollResult {
     pub algorithm: String,
     pub deadline_date: String,
     pub voters: Vec<String>,
+    pub weights: Vec<f32>,              // The weight of each voter above
     pub votes: Vec<String>,
     pub score: Vec<f32>,
     pub score_max: f32,
+    pub score_min: f32,
     pub vote_matrix: Option<VoteMatrix>,
+    pub rounds: Vec<VoteRound>,
+    pub path_strength: Option<PreferenceMatrix>,
+    pub pairwise_matrix: Option<PairwiseMatrix>,
+    pub pairs: Vec<RankedPair>,
+    pub merit_profile: Vec<MeritProfile>,
+    pub stats: Vec<ChoiceStats>,        // In the declared order of the choices
+    pub consensus: Option<Consensus>,
+    pub elected: Vec<String>,
+    pub tied: Vec<String>,              // The choices tied for the winning place (or the last elected place)
+    pub tie_break: Option<String>,      // How the tie was broken, if it was
+    pub rejected: Option<String>,       // Why no winner is declared (quorum not reached or no choice accepted), if so
+}
+
+// The result of one algorithm when comparing all the algorithms
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlgorithmResult {
+    algorithm: VotingAlgorithm,
+    name: String,
+    winners: Vec<String>,
+    ranking: Vec<String>,
+    score: Vec<f32>,
+    error: Option<String>,      // If the algorithm can't be used with these ballots
+    agree: bool,                // If the winners are the same as the poll's algorithm
+}
+
+// All the algorithms run over the same ballots
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PollComparison {
+    pub name: String,
+    pub desc: String,
+    pub user: String,
+    pub algorithm: String,      // The poll's algorithm, used as reference
+    pub results: Vec<AlgorithmResult>,
+    pub unanimous: bool,        // If all the algorithms agree
 }
 
 impl PollResult {
+    // The elected choices, or the winner (or the tied choices if the tie wasn't broken)
+    fn winners(&self) -> Vec<String> {
+        if self.rejected.is_some() {
+            Vec::new()
+        } else if !self.elected.is_empty() {
+            self.elected.clone()
+        } else if !self.tied.is_empty() && self.tie_break.is_none() {
+            self.tied.clone()
+        } else {
+            self.votes.iter().take(1).cloned().collect()
+        }
+    }
+
     fn error(name: &str, err: &str) -> PollResult {
         return PollResult { 
                 name: name.to_string(), 
                 desc: err.to_string(),
                 voters: Vec::new(),
+                weights: Vec::new(),
                 deadline_date: "".to_string(),
                 user: "".to_string(),
                 algorithm: "".to_string(),
                 votes: Vec::new(),
                 score: Vec::new(),
                 score_max: 0f32,
+                score_min: 0f32,
                 vote_matrix: None,
+                rounds: Vec::new(),
+                path_strength: None,
+                pairwise_matrix: None,
+                pairs: Vec::new(),
+                merit_profile: Vec::new(),
+                stats: Vec::new(),
+                consensus: None,
+                elected: Vec::new(),
+                tied: Vec::new(),
+                tie_break: None,
+                rejected: None,
             };
     }
 
-    fn new(poll: &Poll) -> PollResult {
+    // Compute the result of the poll with the given algorithm (usually the poll's algorithm)
+    fn new(poll: &Poll, algorithm: VotingAlgorithm, voter_weights: &HashMap<String, f32>) -> PollResult {
         let def_option = PollOptions { ..Default::default() };
         let opt = poll.options.as_ref().unwrap_or(&def_option);
 
         let mut votes = Vec::new();
+        let mut rounds = Vec::new();
+        let mut path_strength = None;
+        let mut pairs = Vec::new();
+        let mut merit_profile = Vec::new();
+        // Set if the algorithm ranks the choices itself instead of sorting them by score
+        let mut keep_order = false;
+        let mut elected = Vec::new();
+        // The tied choices (index), for the algorithms ranking the choices themselves or breaking a tie inside a round
+        let mut tied = Vec::new();
+        // Set if the tie is inside the algorithm (a finalist or an eliminated choice), such a tie is always broken
+        let mut internal_tie = false;
 
         // We are going to build a 2D matrix here of (row: voter, col:choice, cell: vote) since different algorithm need 
         // different access (some requires row access, some prefer column access)
-        let mut choices = HashSet::new();
-        let mut voters  = HashSet::new();
+        // The choices are ordered by tie break priority: the algorithms keep the first choice when 2 choices are equal, and the final sort is stable
+        let choices = poll.tie_break_order(voter_weights);
+        // The position of each choice in the declared order, the results are displayed in this order when the ranking doesn't matter
+        let declared_choices = poll.choice_names();
+        let declared_index: Vec<usize> = choices.iter().map(|c| declared_choices.iter().position(|x| x == c).unwrap()).collect();
+        // The actual voters, in the order of the allowed participants (then the previous participants, like deleted voters, in the file order)
+        let mut voters: Vec<String> = poll.allowed_participant.iter().filter(|x| poll.choices.iter().any(|c| c.voter.contains(x))).cloned().collect();
         for choice in &poll.choices {
-            // Store all possible choices
-            choices.insert(choice.name.clone());
-            // Store all actual voters
             for voter in &choice.voter {
-                voters.insert(voter.clone());
+                if !voters.contains(voter) {
+                    voters.push(voter.clone());
+                }
             }
         }
-        // Shadow the parameters here so we have an ordered vector here
-        let choices = Vec::from_iter(choices);
-        let voters = Vec::from_iter(voters);
-        // Let's build a matrix here
-        let mut vote_matrix = Array2D::filled_with(0, voters.len(), choices.len());
+        // The weight of each voter's ballot (1 if not specified)
+        let weights: Vec<f32> = voters.iter().map(|x| *voter_weights.get(x).unwrap_or(&1f32)).collect();
+        let total_weight: f32 = weights.iter().sum();
+        // Let's build a matrix here with the raw votes (None for abstentions)
+        let range = poll.score_range();
+        let mut raw_matrix = Array2D::filled_with(None, voters.len(), choices.len());
         // And fill it now
         for choice in &poll.choices {
             let col = choices.iter().position(|x| x == &choice.name).unwrap();
             for i in 0..choice.voter.len() {
                 let row = voters.iter().position(|x| x == &choice.voter[i]).unwrap();
-                vote_matrix[(row, col)] = choice.vote[i];
+                raw_matrix[(row, col)] = choice.vote[i];
+            }
+        }
+        // The algorithms work on points (see ScoreRange), 0 point means the vote isn't counted: that's where the abstention policy applies
+        let mut vote_matrix = Array2D::filled_with(0, voters.len(), choices.len());
+        for (col, raw_col) in raw_matrix.as_columns().iter().enumerate() {
+            let counted: Vec<usize> = raw_col.iter().filter_map(|&x| x).map(|x| range.points(x)).collect();
+            let abstention = match opt.abstention {
+                AbstentionPolicy::Ignore => 0,
+                AbstentionPolicy::Lowest => 1,
+                AbstentionPolicy::Average if counted.is_empty() => 0,
+                AbstentionPolicy::Average => std::cmp::max((counted.iter().sum::<usize>() as f32 / counted.len() as f32).round() as usize, 1),
+            };
+            for (row, vote) in raw_col.iter().enumerate() {
+                vote_matrix[(row, col)] = match vote { Some(x) => range.points(*x), None => abstention };
             }
         }
 
-        let mut score_max = 5f32;
+        let mut score_max = range.max as f32;
+        let mut score_min = 0f32;
+        let seats = std::cmp::min(opt.seats.unwrap_or(1), choices.len());
 
         // First pass, make sure we have completed the vote
-        if opt.show_only_complete_result && vote_matrix.as_row_major().iter().position(|&x| x == 0).is_some() {
+        if opt.show_only_complete_result && !poll.is_complete() {
             return PollResult::error(&poll.name, "<h1>Poll not completed yet</h1>");
         }
 
-        if     poll.voting_algorithm != VotingAlgorithm::Max 
-            && poll.voting_algorithm != VotingAlgorithm::Condorcet 
-            && poll.voting_algorithm != VotingAlgorithm::Binary {
-            for (row, name) in vote_matrix.as_rows().iter().zip(voters.iter()) { 
-                let l: HashSet<&usize> = HashSet::from_iter(row.iter());
-                if l.len() != row.len() {
-                    return PollResult::error(&poll.name, &format!("<h1>Invalid vote result with {:?} algorithm, same vote by {}</h1>", poll.voting_algorithm, name));
+        if !algorithm.allow_same_vote() {
+            for (row, name) in raw_matrix.as_rows().iter().zip(voters.iter()) { 
+                // Abstentions are not votes, so they can't be the same vote
+                let voted: Vec<&i32> = row.iter().filter_map(|x| x.as_ref()).collect();
+                let l: HashSet<&&i32> = HashSet::from_iter(voted.iter());
+                if l.len() != voted.len() {
+                    return PollResult::error(&poll.name, &format!("<h1>Invalid vote result with {:?} algorithm, same vote by {}</h1>", algorithm, name));
                 }
             }
         }
 
-        match poll.voting_algorithm {
+        let raw_columns = raw_matrix.as_columns();
+        let stats = poll.choice_names().iter().map(|name| ChoiceStats::new(name, &raw_columns[choices.iter().position(|x| x == name).unwrap()], &range)).collect();
+        // Computed before running the algorithm, since some algorithms modify the matrix
+        let pairwise_matrix = if opt.show_pairwise_matrix { Some(PairwiseMatrix::new(poll, &choices, &vote_matrix, &weights)) } else { None };
+        let means: Vec<f32> = vote_matrix.as_columns().iter().map(|col| range.vote(counted_mean(col, &weights))).collect();
+
+        match algorithm {
             // This is the max of sum vote, that is the choice with the maximum total number of points wins 
             VotingAlgorithm::Max => {
                 // Compute sum of columns here
                 for (col, name) in vote_matrix.as_columns().iter().zip(choices.iter()) {
-                    votes.push((name.clone(), col.iter().sum::<usize>() as f32 / voters.len() as f32));
+                    votes.push((name.clone(), range.vote(counted_mean(col, &weights))));
                 }
+                score_min = range.min as f32;
             },
             // Used for simple Yes/No polling, the choice with the highest number of Yes wins
             VotingAlgorithm::Binary => {
-                let mut max_score = 0;
+                let mut max_score = 0f32;
                 for (col, name) in vote_matrix.as_columns().iter().zip(choices.iter()) {
-                    let score = col.iter().sum::<usize>();
+                    // No gives 1 point and Yes gives 2 points
+                    let score = col.iter().zip(weights.iter()).filter(|(&x, _)| x > 1).map(|(_, w)| w).sum::<f32>();
                     if score > max_score {
                         max_score = score;
                     }
-                    votes.push((name.clone(), score as f32));
+                    votes.push((name.clone(), score));
                 }
-                score_max = max_score as f32;
+                score_max = max_score;
             }
-            // Bordat is similar to max vote, but all votes are first normalized (worst choice get 1 point, less worst get 2 points and so on) before being summed
-            VotingAlgorithm::Bordat => {
-                // Normalize votes first
-                let rows = vote_matrix.as_rows();
-                for (pos, row) in rows.iter().enumerate() {
-                    let mut vote_for_voter = Vec::from_iter(row.iter().zip(choices.iter()));
-                    vote_for_voter.sort_by(|a, b| a.0.cmp(b.0));
-                    let mut acc = 1;
-                    for (_, choice) in vote_for_voter {
-                        vote_matrix[(pos, choices.iter().position(|x| x == choice).unwrap())] = acc;
-                        acc += 1;
+            // Approval voting, the score is the percentage of the voters approving the choice
+            VotingAlgorithm::Approval => {
+                for (col, name) in vote_matrix.as_columns().iter().zip(choices.iter()) {
+                    let approvals = col.iter().zip(weights.iter()).filter(|(&x, _)| x > 1).map(|(_, w)| w).sum::<f32>();
+                    votes.push((name.clone(), if total_weight > 0f32 { approvals * 100f32 / total_weight } else { 0f32 }));
+                }
+                score_max = 100f32;
+            },
+            // Bordat is similar to max vote, but all votes are first normalized (best choice get N points, next get N-1 points and so on) before being summed
+            // The Borda variants only differ by the points given to each rank
+            VotingAlgorithm::Bordat | VotingAlgorithm::Borda | VotingAlgorithm::Dowdall | VotingAlgorithm::ModifiedBorda => {
+                let n = choices.len();
+                // The points for the rank (from 0 for the best choice) among the ranked choices
+                let rank_points = |rank: usize, ranked: usize| match algorithm {
+                    VotingAlgorithm::Borda => (n - 1 - rank) as f32,
+                    VotingAlgorithm::Dowdall => 1f32 / (rank + 1) as f32,
+                    VotingAlgorithm::ModifiedBorda => (ranked - rank) as f32,
+                    _ => (n - rank) as f32,
+                };
+                let mut score = vec![0f32; n];
+                for (row, weight) in vote_matrix.as_rows().iter().zip(weights.iter()) {
+                    for (col, points) in borda_points(row, &rank_points).iter().enumerate() {
+                        score[col] += points * weight;
                     }
                 }
-
-                // Compute sum of columns here
-                for (col, name) in vote_matrix.as_columns().iter().zip(choices.iter()) {
-                    votes.push((name.clone(), col.iter().sum::<usize>() as f32 / voters.len() as f32));
+                // The mean points per voter
+                for (col, name) in choices.iter().enumerate() {
+                    votes.push((name.clone(), score[col] / total_weight));
                 }
+                score_max = match algorithm {
+                    VotingAlgorithm::Borda => (n - 1) as f32,
+                    VotingAlgorithm::Dowdall => 1f32,
+                    _ => n as f32,
+                };
             },
             // This is similar to mean consensus vote, that is each choice is compared to each other choice individually and the last winner wins the vote           
             VotingAlgorithm::Condorcet => {
@@ -337,11 +987,11 @@ impl PollResult {
                 let cols = vote_matrix.as_columns();
 
                 // Dumb implementation in O(N^2) here, sorry, but it's easier and the number of choice will be limited anyway
-                let min_score = voters.len() / 2; 
+                let min_score = total_weight / 2f32; 
                 for (col, name) in cols.iter().zip(choices.iter()) {
                     let mut score_duel = 0;
                     for other_col in &cols {
-                        let score = col.iter().zip(other_col.iter()).map(|(a, b)| if a > b { 1 } else { 0 }).sum::<usize>();
+                        let score = col.iter().zip(other_col.iter()).zip(weights.iter()).filter(|((a, b), _)| prefers(**a, **b)).map(|(_, w)| w).sum::<f32>();
                         if score > min_score { 
                             score_duel += 1; 
                         }
@@ -353,56 +1003,625 @@ impl PollResult {
             },
             // This is similar to only select the best choice vote, that is only the preferred choice is kept for each voter regardless of the other choice, and the choice with the most voters wins      
             VotingAlgorithm::FirstChoice => {
-                let mut vote_per_voter = vec![0;choices.len()];
+                let mut vote_per_voter = vec![0f32;choices.len()];
                 let rows = vote_matrix.as_rows();
 
-                for row in rows.iter() {
+                for (row, weight) in rows.iter().zip(weights.iter()) {
                     let max_value = row.iter().max().unwrap();
+                    if *max_value == 0 {
+                        // Nothing counted for this voter
+                        continue;
+                    }
                     let col_pos = row.iter().position(|x| x == max_value).unwrap();
-                    vote_per_voter[col_pos] += 1;
+                    vote_per_voter[col_pos] += weight;
                 }
                 for (vote, name) in vote_per_voter.iter().zip(choices.iter()) {
-                    votes.push((name.clone(), *vote as f32));                    
+                    votes.push((name.clone(), *vote));                    
                 }
 
                 score_max = choices.len() as f32;
             },
             // This is similar to the French voting system, that is the choice counted per voters and vote, and only the 2 best choice are kept, then the other choice value are dispatched to compute the statistics and select the highest score    
             VotingAlgorithm::FrenchSystem => {
-                return PollResult::error(&poll.name, &format!("<h1>This {:?} algorithm isn't implemented yet</h1>", poll.voting_algorithm));
+                let rows = vote_matrix.as_rows();
+                // First round, only the preferred choice of each voter is counted
+                let mut ballots = vec![0f32;choices.len()];
+                let mut no_preference = 0f32;
+                for (row, weight) in rows.iter().zip(weights.iter()) {
+                    let max_value = row.iter().max().unwrap();
+                    if *max_value == 0 {
+                        // Only missing choices for this voter
+                        no_preference += weight;
+                        continue;
+                    }
+                    ballots[row.iter().position(|x| x == max_value).unwrap()] += weight;
+                }
+                let mut ranking = Vec::from_iter(0..choices.len());
+                ranking.sort_by(|a, b| ballots[*b].partial_cmp(&ballots[*a]).unwrap_or(std::cmp::Ordering::Equal));
+                let second_round = ranking.len() > 1 && ballots[ranking[0]] * 2f32 <= total_weight - no_preference;
+                if second_round && ranking.len() > 2 && ballots[ranking[1]] == ballots[ranking[2]] {
+                    // The second finalist is decided by the tie break
+                    tied = ranking.iter().cloned().filter(|&i| ballots[i] == ballots[ranking[1]]).collect();
+                    internal_tie = true;
+                }
+                rounds.push(VoteRound {
+                    choices: ranking.iter().map(|&i| choices[i].clone()).collect(),
+                    score: ranking.iter().map(|&i| ballots[i]).collect(),
+                    no_preference: no_preference,
+                    eliminated: if second_round { ranking.iter().skip(2).map(|&i| choices[i].clone()).collect() } else { Vec::new() },
+                    elected: Vec::new(),
+                });
+
+                // If no choice has the absolute majority, the 2 best choices go to the second round where each ballot 
+                // goes to the finalist the voter rated higher
+                if second_round {
+                    let (first, second) = (ranking[0], ranking[1]);
+                    ballots[first] = 0f32;
+                    ballots[second] = 0f32;
+                    no_preference = 0f32;
+                    for (row, weight) in rows.iter().zip(weights.iter()) {
+                        if prefers(row[first], row[second]) {
+                            ballots[first] += weight;
+                        } else if prefers(row[second], row[first]) {
+                            ballots[second] += weight;
+                        } else {
+                            no_preference += weight;
+                        }
+                    }
+                    rounds.push(VoteRound {
+                        choices: vec![choices[first].clone(), choices[second].clone()],
+                        score: vec![ballots[first], ballots[second]],
+                        no_preference: no_preference,
+                        eliminated: Vec::new(),
+                        elected: Vec::new(),
+                    });
+                }
+
+                // A finalist can't get less ballots in the second round than in the first round, so the usual sorting below gives the right order
+                for (ballot, name) in ballots.iter().zip(choices.iter()) {
+                    votes.push((name.clone(), *ballot));
+                }
+
+                score_max = total_weight;
             },
             // In this mode, the choice with the lowest acceptance is eliminated and the other vote with a lower value are transfered to the other choice, repeat until only one remains
             VotingAlgorithm::SuccessiveElimination => {
-                return PollResult::error(&poll.name, &format!("<h1>This {:?} algorithm isn't implemented yet</h1>", poll.voting_algorithm));
-            }     
+                let rows = vote_matrix.as_rows();
+                // Used to break ties when eliminating a choice
+                let points: Vec<f32> = vote_matrix.as_columns().iter().map(|col| weighted_sum(col, &weights)).collect();
+                let mut remaining = Vec::from_iter(0..choices.len());
+                let mut eliminated = Vec::new();
+                while !remaining.is_empty() {
+                    // Each ballot goes to the voter's preferred choice among the remaining ones (missing choices are not ranked)
+                    let mut ballots = vec![0f32;choices.len()];
+                    let mut no_preference = 0f32;
+                    for (row, weight) in rows.iter().zip(weights.iter()) {
+                        match remaining.iter().filter(|&&i| row[i] > 0).max_by_key(|&&i| row[i]) {
+                            Some(&i) => ballots[i] += weight,
+                            None => no_preference += weight,
+                        }
+                    }
+                    remaining.sort_by(|a, b| ballots[*b].partial_cmp(&ballots[*a]).unwrap_or(std::cmp::Ordering::Equal));
+                    let mut round = VoteRound {
+                        choices: remaining.iter().map(|&i| choices[i].clone()).collect(),
+                        score: remaining.iter().map(|&i| ballots[i]).collect(),
+                        no_preference: no_preference,
+                        eliminated: Vec::new(),
+                        elected: Vec::new(),
+                    };
+
+                    if remaining.len() == 1 || ballots[remaining[0]] * 2f32 > total_weight - no_preference {
+                        // We have a majority, the remaining choices are ranked by their ballots in this last round
+                        rounds.push(round);
+                        for &i in remaining.iter() {
+                            votes.push((choices[i].clone(), ballots[i]));
+                        }
+                        break;
+                    }
+                    // Else eliminate the choice with the lowest number of ballots (or the lowest total points in case of tie)
+                    // The remaining choices are in the tie break order for the same ballots, so the last one of the tied choices is eliminated
+                    let worst = *remaining.iter().rev().min_by(|&&a, &&b| ballots[a].partial_cmp(&ballots[b]).unwrap_or(std::cmp::Ordering::Equal).then(points[a].partial_cmp(&points[b]).unwrap_or(std::cmp::Ordering::Equal))).unwrap();
+                    let same: Vec<usize> = remaining.iter().cloned().filter(|&i| ballots[i] == ballots[worst] && points[i] == points[worst]).collect();
+                    if tied.is_empty() && same.len() > 1 {
+                        tied = same;
+                        internal_tie = true;
+                    }
+                    round.eliminated.push(choices[worst].clone());
+                    rounds.push(round);
+                    eliminated.push((worst, ballots[worst]));
+                    remaining.retain(|&i| i != worst);
+                }
+                // The last eliminated choices are the best ones. A choice can't lose ballots while the others are eliminated
+                // so the usual sorting below keeps this order
+                for (i, ballot) in eliminated.iter().rev() {
+                    votes.push((choices[*i].clone(), *ballot));
+                }
+
+                score_max = total_weight;
+            },
+            // Each choice is compared to each other choice, and the strongest path of pairwise victories between 2 choices decides which one is preferred
+            VotingAlgorithm::Schulze => {
+                let preferences = pairwise_preferences(&vote_matrix, &weights);
+                let count = choices.len();
+                // Strength of the direct path first, a path only exists if the choice wins the duel
+                let mut strength = vec![vec![0f32;count];count];
+                for i in 0..count {
+                    for j in 0..count {
+                        if i != j && preferences[i][j] > preferences[j][i] {
+                            strength[i][j] = preferences[i][j];
+                        }
+                    }
+                }
+                // Then find the strongest path (Floyd-Warshall like, the strength of a path is its weakest link)
+                for i in 0..count {
+                    for j in 0..count {
+                        if i == j { continue; }
+                        for k in 0..count {
+                            if i != k && j != k {
+                                strength[j][k] = strength[j][k].max(strength[j][i].min(strength[i][k]));
+                            }
+                        }
+                    }
+                }
+                // The relation is transitive, so counting the choices beaten by each choice gives the complete ranking
+                for (i, name) in choices.iter().enumerate() {
+                    let wins = (0..count).filter(|&j| strength[i][j] > strength[j][i]).count();
+                    votes.push((name.clone(), wins as f32));
+                }
+
+                score_max = count.saturating_sub(1) as f32;
+                path_strength = Some(PreferenceMatrix::new(poll, &choices, &strength));
+            },
+            // The pairwise victories are sorted by margin and locked in a graph unless they create a cycle, the graph then gives the ranking
+            VotingAlgorithm::RankedPairs => {
+                let preferences = pairwise_preferences(&vote_matrix, &weights);
+                let count = choices.len();
+                let mut victories = Vec::new();
+                for i in 0..count {
+                    for j in 0..count {
+                        if preferences[i][j] > preferences[j][i] {
+                            victories.push((i, j, preferences[i][j] - preferences[j][i]));
+                        }
+                    }
+                }
+                // Largest margin first, and in case of equal margins, the largest number of voters preferring the winner first
+                victories.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal)
+                                            .then(preferences[b.0][b.1].partial_cmp(&preferences[a.0][a.1]).unwrap_or(std::cmp::Ordering::Equal)));
+
+                let mut locked = vec![vec![false;count];count];
+                let mut processed = Vec::new();
+                for (winner, loser, margin) in victories {
+                    // Locking winner => loser creates a cycle if the loser already leads to the winner
+                    let cycle = reachable_choices(&locked, loser)[winner];
+                    if !cycle {
+                        locked[winner][loser] = true;
+                    }
+                    processed.push((winner, loser, margin, !cycle));
+                }
+                // The tie break order only decides which of the pairs with the same margin and support is locked first, they are shown in the declared order
+                processed.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal)
+                                            .then(preferences[b.0][b.1].partial_cmp(&preferences[a.0][a.1]).unwrap_or(std::cmp::Ordering::Equal))
+                                            .then((declared_index[a.0], declared_index[a.1]).cmp(&(declared_index[b.0], declared_index[b.1]))));
+                for (winner, loser, margin, is_locked) in processed {
+                    pairs.push(RankedPair { winner: choices[winner].clone(), loser: choices[loser].clone(), margin: margin, locked: is_locked });
+                }
+                // The locked graph has no cycle, so counting the choices reachable from each choice gives the complete ranking
+                for (i, name) in choices.iter().enumerate() {
+                    let wins = reachable_choices(&locked, i).iter().enumerate().filter(|&(j, &r)| r && j != i).count();
+                    votes.push((name.clone(), wins as f32));
+                }
+
+                score_max = count.saturating_sub(1) as f32;
+            },
+            // Each choice gets the median of its grades (missing choices are not graded)
+            VotingAlgorithm::MajorityJudgment => {
+                let cols = vote_matrix.as_columns();
+                let grade_max = range.span();
+                let mut majority_values = Vec::new();
+                for (col, name) in cols.iter().zip(choices.iter()) {
+                    // The grades with the weight of their voter
+                    let mut grades: Vec<(usize, f32)> = col.iter().cloned().zip(weights.iter().cloned()).filter(|&(x, _)| x > 0).collect();
+                    grades.sort_by_key(|x| x.0);
+                    let mut profile: Vec<MeritGrade> = (0..grade_max).map(|i| MeritGrade {
+                        grade: range.min + i as i32,
+                        count: 0f32,
+                        hue: i * 120 / std::cmp::max(grade_max - 1, 1),
+                    }).collect();
+                    for &(grade, weight) in grades.iter() {
+                        profile[std::cmp::min(grade, grade_max) - 1].count += weight;
+                    }
+                    // An ungraded choice gets the lowest grade
+                    let median = if grades.is_empty() { range.min } else { range.vote(grades[lower_median(&grades)].0 as f32) as i32 };
+                    merit_profile.push(MeritProfile { choice: name.clone(), median: median, total: grades.iter().map(|x| x.1).sum(), grades: profile });
+
+                    // The majority value is the sequence of medians found when removing the median grade one after the other
+                    let mut majority_value = Vec::new();
+                    while !grades.is_empty() {
+                        let median = grades.remove(lower_median(&grades));
+                        majority_value.push(median.0);
+                    }
+                    majority_values.push(majority_value);
+                }
+
+                // Comparing the majority values breaks the ties between the choices with the same median
+                // (the sort is stable, so choices with the same majority value keep the tie break order)
+                let mut ranking = Vec::from_iter(0..choices.len());
+                ranking.sort_by(|a, b| majority_values[*b].cmp(&majority_values[*a]));
+                // Only the same majority value is a tie, not the same median
+                if ranking.len() > 1 && majority_values[ranking[0]] == majority_values[ranking[1]] {
+                    tied = ranking.iter().cloned().filter(|&i| majority_values[i] == majority_values[ranking[0]]).collect();
+                }
+                for i in ranking {
+                    votes.push((choices[i].clone(), merit_profile[i].median as f32));
+                }
+                merit_profile.sort_by_key(|x| declared_choices.iter().position(|c| c == &x.choice));
+                keep_order = true;
+                score_min = range.min as f32;
+            },
+            // The scoring round selects the 2 choices with the maximum total number of points, then each ballot goes to the finalist it rated higher
+            VotingAlgorithm::Star => {
+                // The mean of the counted votes, so ignored abstentions don't lower the score
+                let points: Vec<f32> = vote_matrix.as_columns().iter().map(|col| counted_mean(col, &weights)).collect();
+                let mut ranking = Vec::from_iter(0..choices.len());
+                ranking.sort_by(|a, b| points[*b].partial_cmp(&points[*a]).unwrap_or(std::cmp::Ordering::Equal));
+                if ranking.len() > 2 && points[ranking[1]] == points[ranking[2]] {
+                    // The second finalist is decided by the tie break
+                    tied = ranking.iter().cloned().filter(|&i| points[i] == points[ranking[1]]).collect();
+                    internal_tie = true;
+                }
+                rounds.push(VoteRound {
+                    choices: ranking.iter().map(|&i| choices[i].clone()).collect(),
+                    score: ranking.iter().map(|&i| range.vote(points[i])).collect(),
+                    no_preference: 0f32,
+                    eliminated: ranking.iter().skip(2).map(|&i| choices[i].clone()).collect(),
+                    elected: Vec::new(),
+                });
+
+                if ranking.len() > 1 {
+                    // Automatic runoff between the 2 finalists
+                    let (first, second) = (ranking[0], ranking[1]);
+                    let mut ballots = [0f32, 0f32];
+                    let mut no_preference = 0f32;
+                    for (row, weight) in vote_matrix.as_rows().iter().zip(weights.iter()) {
+                        if prefers(row[first], row[second]) {
+                            ballots[0] += weight;
+                        } else if prefers(row[second], row[first]) {
+                            ballots[1] += weight;
+                        } else {
+                            no_preference += weight;
+                        }
+                    }
+                    rounds.push(VoteRound {
+                        choices: vec![choices[first].clone(), choices[second].clone()],
+                        score: vec![ballots[0], ballots[1]],
+                        no_preference: no_preference,
+                        eliminated: Vec::new(),
+                        elected: Vec::new(),
+                    });
+                    // The finalist preferred by most voters wins (or the one with the most points in case of tie)
+                    if ballots[1] > ballots[0] {
+                        ranking.swap(0, 1);
+                    } else if ballots[1] == ballots[0] && points[first] == points[second] {
+                        // A tie for the winning place is more important than a tie for the second finalist
+                        tied = vec![first, second];
+                        internal_tie = false;
+                    }
+                }
+
+                // The runoff winner can have less points than the other finalist, so don't sort by score here
+                for i in ranking {
+                    votes.push((choices[i].clone(), range.vote(points[i])));
+                }
+                keep_order = true;
+                score_min = range.min as f32;
+            },
+            // Single transferable vote with Droop quota, the surplus of the elected choices is transfered by reducing the weight of their ballots
+            VotingAlgorithm::SingleTransferableVote => {
+                let rows = vote_matrix.as_rows();
+                // Used to break ties when eliminating a choice
+                let points: Vec<f32> = vote_matrix.as_columns().iter().map(|col| weighted_sum(col, &weights)).collect();
+                // Each ballot ranks the choices by decreasing vote (missing choices are not ranked)
+                let ballots: Vec<Vec<usize>> = rows.iter().map(|row| {
+                                                    let mut ranking: Vec<usize> = (0..choices.len()).filter(|&i| row[i] > 0).collect();
+                                                    ranking.sort_by(|a, b| row[*b].cmp(&row[*a]));
+                                                    ranking
+                                               }).collect();
+                // Each ballot starts with its voter's weight
+                let mut ballot_weights = weights.clone();
+                let mut eliminated = Vec::new();
+                let quota = (ballots.iter().zip(weights.iter()).filter(|(x, _)| !x.is_empty()).map(|(_, w)| w).sum::<f32>() / (seats + 1) as f32).floor() + 1f32;
+
+                let mut remaining = Vec::from_iter(0..choices.len());
+                // The last tally of each choice, before it was elected or eliminated
+                let mut score = vec![0f32;choices.len()];
+                while elected.len() < seats && !remaining.is_empty() {
+                    // Each ballot goes, with its current weight, to the voter's preferred remaining choice
+                    let mut tally = vec![0f32;choices.len()];
+                    let mut holder = vec![None;ballots.len()];
+                    let mut no_preference = 0f32;
+                    for (ballot, ranking) in ballots.iter().enumerate() {
+                        match ranking.iter().find(|i| remaining.contains(i)) {
+                            Some(&i) => { tally[i] += ballot_weights[ballot]; holder[ballot] = Some(i); },
+                            None => no_preference += ballot_weights[ballot],
+                        }
+                    }
+                    remaining.sort_by(|a, b| tally[*b].partial_cmp(&tally[*a]).unwrap_or(std::cmp::Ordering::Equal));
+                    for &i in remaining.iter() {
+                        score[i] = tally[i];
+                    }
+                    let mut round = VoteRound {
+                        choices: remaining.iter().map(|&i| choices[i].clone()).collect(),
+                        score: remaining.iter().map(|&i| tally[i]).collect(),
+                        no_preference: no_preference,
+                        eliminated: Vec::new(),
+                        elected: Vec::new(),
+                    };
+
+                    if remaining.len() + elected.len() <= seats {
+                        // Not enough choices left to fill the seats, they are all elected
+                        for &i in remaining.iter() {
+                            round.elected.push(choices[i].clone());
+                            elected.push(i);
+                        }
+                        remaining.clear();
+                    } else if tally[remaining[0]] >= quota {
+                        // Elected, the ballots for this choice are transfered with the surplus fraction of their weight
+                        let best = remaining[0];
+                        let ratio = (tally[best] - quota) / tally[best];
+                        for (ballot, choice) in holder.iter().enumerate() {
+                            if *choice == Some(best) {
+                                ballot_weights[ballot] *= ratio;
+                            }
+                        }
+                        round.elected.push(choices[best].clone());
+                        elected.push(best);
+                        remaining.retain(|&i| i != best);
+                    } else {
+                        // Eliminate the choice with the lowest number of ballots (or the lowest total points in case of tie), the last one in the tie break order
+                        let worst = *remaining.iter().rev().min_by(|&&a, &&b| tally[a].partial_cmp(&tally[b]).unwrap_or(std::cmp::Ordering::Equal).then(points[a].partial_cmp(&points[b]).unwrap_or(std::cmp::Ordering::Equal))).unwrap();
+                        let same: Vec<usize> = remaining.iter().cloned().filter(|&i| tally[i] == tally[worst] && points[i] == points[worst]).collect();
+                        if tied.is_empty() && same.len() > 1 {
+                            tied = same;
+                            internal_tie = true;
+                        }
+                        round.eliminated.push(choices[worst].clone());
+                        eliminated.push(worst);
+                        remaining.retain(|&i| i != worst);
+                    }
+                    rounds.push(round);
+                }
+
+                // Elected choices first, then the choices that were neither elected nor eliminated and the eliminated choices, last eliminated first
+                for &i in elected.iter().chain(remaining.iter()).chain(eliminated.iter().rev()) {
+                    votes.push((choices[i].clone(), score[i]));
+                }
+                keep_order = true;
+                score_max = total_weight;
+            },
+            // Sequential proportional approval: a ballot approving k elected choices only counts for 1/(k+1) in the next round
+            VotingAlgorithm::SequentialProportionalApproval => {
+                let rows = vote_matrix.as_rows();
+                let mut remaining = Vec::from_iter(0..choices.len());
+                // The weighted approvals of each choice in the round it was elected (or in the last round)
+                let mut score = vec![0f32;choices.len()];
+                while elected.len() < seats && !remaining.is_empty() {
+                    let mut approvals = vec![0f32;choices.len()];
+                    let mut no_preference = 0f32;
+                    for (row, voter_weight) in rows.iter().zip(weights.iter()) {
+                        // No gives 1 point and Yes gives 2 points
+                        let weight = voter_weight / (1 + elected.iter().filter(|&&i| row[i] > 1).count()) as f32;
+                        if remaining.iter().all(|&i| row[i] <= 1) {
+                            no_preference += voter_weight;
+                        }
+                        for &i in remaining.iter() {
+                            if row[i] > 1 {
+                                approvals[i] += weight;
+                            }
+                        }
+                    }
+                    remaining.sort_by(|a, b| approvals[*b].partial_cmp(&approvals[*a]).unwrap_or(std::cmp::Ordering::Equal));
+                    for &i in remaining.iter() {
+                        score[i] = approvals[i];
+                    }
+                    let best = remaining[0];
+                    if tied.is_empty() && remaining.len() > seats - elected.len() && approvals[remaining[seats - elected.len()]] == approvals[best] {
+                        // Some choices with the same approvals as the elected one won't be elected
+                        tied = remaining.iter().cloned().filter(|&i| approvals[i] == approvals[best]).collect();
+                    }
+                    rounds.push(VoteRound {
+                        choices: remaining.iter().map(|&i| choices[i].clone()).collect(),
+                        score: remaining.iter().map(|&i| approvals[i]).collect(),
+                        no_preference: no_preference,
+                        eliminated: Vec::new(),
+                        elected: vec![choices[best].clone()],
+                    });
+                    elected.push(best);
+                    remaining.remove(0);
+                }
+
+                for &i in elected.iter().chain(remaining.iter()) {
+                    votes.push((choices[i].clone(), score[i]));
+                }
+                keep_order = true;
+                score_max = total_weight;
+            },
         }
+        // In a round, the choices with the same score are shown in the declared order (the tie break order only decides the ranking)
+        for round in rounds.iter_mut() {
+            let mut entries: Vec<(String, f32)> = round.choices.iter().cloned().zip(round.score.iter().cloned()).collect();
+            let mut start = 0;
+            while start < entries.len() {
+                let end = start + entries[start..].iter().take_while(|x| x.1 == entries[start].1).count();
+                entries[start..end].sort_by_key(|x| declared_choices.iter().position(|c| c == &x.0));
+                start = end;
+            }
+            round.choices = entries.iter().map(|x| x.0.clone()).collect();
+            round.score = entries.iter().map(|x| x.1).collect();
+        }
+        let mut elected: Vec<String> = elected.iter().map(|&i| choices[i].clone()).collect();
+        let mut tied: Vec<String> = tied.iter().map(|&i| choices[i].clone()).collect();
+
         // Reverse sorting
-        votes.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        if !keep_order {
+            votes.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+            // A tie for the winning place (or the last elected place) is decided by the tie break
+            let last = std::cmp::max(elected.len(), 1) - 1;
+            if votes.len() > last + 1 && votes[last].1 == votes[last + 1].1 {
+                // A tie for the winning place is more important than a tie inside the algorithm
+                tied = votes.iter().filter(|x| x.1 == votes[last].1).map(|x| x.0.clone()).collect();
+                internal_tie = false;
+            }
+        }
+        let consensus = Some(Consensus::new(&raw_matrix, &stats, votes.first().map(|x| &x.0)));
+        // Check the quorum, a choice which isn't accepted can't be elected
+        let mut rejected = None;
+        if let Some(quorum) = opt.quorum {
+            let voted = poll.allowed_participant.iter().filter(|x| voters.contains(x)).count();
+            rejected = quorum.participation(voted, poll.allowed_participant.len());
+            if let (None, Some(min)) = (&rejected, quorum.min_score) {
+                let accepted = |name: &String| means[choices.iter().position(|x| x == name).unwrap()] >= min;
+                if elected.is_empty() {
+                    // The tied choices are all winners if the tie isn't broken
+                    let winners = if !tied.is_empty() && !internal_tie && opt.tie_break == TieBreak::Tie { tied.clone() } else { votes.iter().take(1).map(|x| x.0.clone()).collect() };
+                    if !winners.iter().all(|x| accepted(x)) {
+                        rejected = Some(format!("No choice met the acceptance threshold: a mean vote of {}", min));
+                    }
+                } else {
+                    elected.retain(|x| accepted(x));
+                    if elected.is_empty() {
+                        rejected = Some(format!("No choice met the acceptance threshold: a mean vote of {}", min));
+                    }
+                }
+            }
+        }
+        let tie_break = match (tied.is_empty(), opt.tie_break) {
+            (true, _) => None,
+            // A tie inside the algorithm can't be kept, the declared order is used
+            (false, TieBreak::Tie) if internal_tie => Some("the declared order of the choices".to_string()),
+            (false, TieBreak::Tie) => None,
+            (false, TieBreak::DeclaredOrder) => Some("the declared order of the choices".to_string()),
+            (false, TieBreak::Lottery) => Some(format!("a lottery (seed {})", opt.tie_break_seed.map_or("from the poll name".to_string(), |x| x.to_string()))),
+            (false, TieBreak::Fallback) => Some(format!("the {:?} algorithm", opt.tie_break_algorithm.unwrap_or(VotingAlgorithm::Max))),
+        };
 
         PollResult { 
             name: poll.name.clone(),
             desc: poll.desc.clone(),
             voters: poll.allowed_participant.clone(),
+            weights: poll.allowed_participant.iter().map(|x| *voter_weights.get(x).unwrap_or(&1f32)).collect(),
             deadline_date: format!("{}", poll.deadline_date.format(DEADLINE_FORMAT)),
             user: "".to_string(),
-            algorithm: format!("{:?}", poll.voting_algorithm),
+            algorithm: format!("{:?}", algorithm),
             votes: votes.iter().map(|a| a.0.clone()).collect(),
             score: votes.iter().map(|a| if a.1.is_nan() { 0f32 } else { a.1 }).collect(),
             score_max: score_max,
+            score_min: score_min,
             vote_matrix: match poll.options.as_ref().unwrap_or(&Default::default()).show_vote_matrix {
-                true => Some(VoteMatrix {
-                    voters: voters,
-                    choices: choices,
-                    matrix: vote_matrix.as_rows(),
-                }),
+                true => Some(VoteMatrix::new(poll, &voters, &choices, &raw_matrix)),
                 false => None
             }, 
+            rounds: rounds,
+            path_strength: path_strength,
+            pairwise_matrix: pairwise_matrix,
+            pairs: pairs,
+
+            merit_profile: merit_profile,
+            stats: stats,
+            consensus: consensus,
+            elected: elected,
+            tied: tied,
+            tie_break: tie_break,
+            rejected: rejected,
         }  
     }
 }
 
+// The points of each choice in a voter's row: the counted choices are ranked by decreasing points, tied choices share the mean points of their ranks
+// and the uncounted choices (abstentions) get no point. The points for a rank are given by rank_points(rank, number of ranked choices)
+fn borda_points<F>(row: &Vec<usize>, rank_points: &F) -> Vec<f32> where F: Fn(usize, usize) -> f32 {
+    let ranked = row.iter().filter(|&&x| x > 0).count();
+    row.iter().map(|&x| {
+        if x == 0 {
+            return 0f32;
+        }
+        let better = row.iter().filter(|&&y| y > x).count();
+        let same = row.iter().filter(|&&y| y == x).count();
+        (better..better + same).map(|rank| rank_points(rank, ranked)).sum::<f32>() / same as f32
+    }).collect()
+}
+
+// Count, for each pair of choices (A, B), the number of voters who voted more for A than for B (weighted)
+fn pairwise_preferences(vote_matrix: &Array2D<usize>, weights: &Vec<f32>) -> Vec<Vec<f32>> {
+    let cols = vote_matrix.as_columns();
+    let mut preferences = vec![vec![0f32;cols.len()];cols.len()];
+    for (i, col) in cols.iter().enumerate() {
+        for (j, other_col) in cols.iter().enumerate() {
+            preferences[i][j] = col.iter().zip(other_col.iter()).zip(weights.iter()).filter(|((a, b), _)| prefers(**a, **b)).map(|(_, w)| w).sum();
+        }
+    }
+    preferences
+}
+
+// If a voter prefers a choice to another one, given the points of both (a voter doesn't prefer anything to an uncounted vote)
+fn prefers(points: usize, other_points: usize) -> bool {
+    other_points > 0 && points > other_points
+}
 
+// The weighted sum of the points of a choice
+fn weighted_sum(col: &Vec<usize>, weights: &Vec<f32>) -> f32 {
+    col.iter().zip(weights.iter()).map(|(&x, w)| x as f32 * w).sum()
+}
 
+// The weighted mean of the counted points of a choice (or 0 if nothing is counted)
+fn counted_mean(col: &Vec<usize>, weights: &Vec<f32>) -> f32 {
+    let counted: f32 = col.iter().zip(weights.iter()).filter(|(&x, _)| x > 0).map(|(_, w)| w).sum();
+    if counted == 0f32 { 0f32 } else { weighted_sum(col, weights) / counted }
+}
+
+// The position of the lower median in the given list of (grade, weight) sorted by grade: the first grade reaching half of the total weight
+fn lower_median(sorted: &Vec<(usize, f32)>) -> usize {
+    let total: f32 = sorted.iter().map(|x| x.1).sum();
+    let mut acc = 0f32;
+    for (i, x) in sorted.iter().enumerate() {
+        acc += x.1;
+        if acc * 2f32 >= total {
+            return i;
+        }
+    }
+    0
+}
+
+// Find all the choices that can be reached from the given choice in the graph (graph[a][b] is true if there is an edge from a to b)
+fn reachable_choices(graph: &Vec<Vec<bool>>, from: usize) -> Vec<bool> {
+    let mut reached = vec![false;graph.len()];
+    let mut stack = vec![from];
+    while let Some(current) = stack.pop() {
+        for (next, &edge) in graph[current].iter().enumerate() {
+            if edge && !reached[next] {
+                reached[next] = true;
+                stack.push(next);
+            }
+        }
+    }
+    reached
+}
+
+
+
+
+// A small xorshift random generator, so the lottery gives the same result for the same seed everywhere
+struct XorShift(u64);
+impl XorShift {
+    fn new(seed: u64) -> XorShift { XorShift(if seed == 0 { 0x9e3779b97f4a7c15 } else { seed }) }
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    // A random number in [0, n)
+    fn below(&mut self, n: usize) -> usize { (self.next() % n as u64) as usize }
+}
 
 // Our custom date formatter
 mod date_serde {
@@ -428,13 +1647,12 @@ mod date_serde {
     }
 }
 
-pub fn build_desc(description: &Option<String>, desc_markdown: &Option<String>, path: &Path, skip_summary: bool) -> Result<String, RPError> {
+pub fn build_desc(storage: &dyn Storage, description: &Option<String>, desc_markdown: &Option<String>, path: &Path, skip_summary: bool) -> Result<String, RPError> {
     if description.is_none() && desc_markdown.is_none() {
         Ok(path.to_str().unwrap().to_string())
     } else if description.is_none() && desc_markdown.is_some() {
-        // Read the given file and convert to HTML here
-        let rel_path_to_md_file = path.with_file_name(desc_markdown.as_ref().unwrap().clone());
-        let mut md_content = fs::read_to_string(rel_path_to_md_file)?;
+        // Read the given markdown (stored along the poll) and convert to HTML here
+        let mut md_content = storage.read_markdown(desc_markdown.as_ref().unwrap())?;
         if !skip_summary {
             // Stop at the second header found
             md_content = md_content.split("\n#").nth(0).unwrap().to_string();
@@ -445,12 +1663,12 @@ pub fn build_desc(description: &Option<String>, desc_markdown: &Option<String>,
     }
 }
 
-pub fn parse_poll_file(path: &Path) -> Result<Poll, RPError> {
-    let content = fs::read_to_string(path)?;
-    //let poll: Poll = serde_yaml::from_reader(fs::File::open(path).expect("Unable to read file"))?;
-    let mut poll: Poll = serde_yaml::from_str(&content)?;
-    // If we don't have a description, let's fetch from markdown
-    poll.desc = build_desc(&poll.description, &poll.desc_markdown, path, false)?;
+// Parse a poll stored at the given path (the path's file stem is the poll's name for the storage)
+pub fn parse_poll(content: &str, path: &Path) -> Result<Poll, RPError> {
+    let mut value: serde_yaml::Value = serde_yaml::from_str(content)?;
+    migration::migrate_poll(&mut value)?;
+    let mut poll: Poll = serde_yaml::from_value(value)?;
+    poll.validate()?;
     poll.filepath = path.to_str().unwrap().to_string();
     poll.filename = match path.file_stem() {
                         Some(path) => Some(path.to_str().unwrap().to_string()),
@@ -459,192 +1677,272 @@ pub fn parse_poll_file(path: &Path) -> Result<Poll, RPError> {
     return Ok(poll);
 }
 
-pub fn find_poll_desc(name: &str) -> Result<Poll, RPError> {
-    // Get all poll and find the one with the good file
-    let polls = get_poll_list()?;
-    for entry in polls {
-        match Path::new(&entry.filepath).file_stem() {
-            Some(n) => {
-                if name == n {
-                    return Ok(entry);
-                }
-            },
-            None => {},
-        }
+// If we don't have a description, let's fetch from markdown (unless the storage already did)
+pub fn fill_desc(storage: &dyn Storage, mut poll: Poll) -> Result<Poll, RPError> {
+    if poll.desc.is_empty() {
+        poll.desc = build_desc(storage, &poll.description, &poll.desc_markdown, Path::new(&poll.filepath), false)?;
     }
-    return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", name))));
+    return Ok(poll);
+}
+
+pub fn find_poll_desc(storage: &dyn Storage, name: &str) -> Result<Poll, RPError> {
+    return fill_desc(storage, storage.find_poll(name)?);
 }
 
-pub fn get_poll_desc(name: &str, skip_summary: bool) -> Result<ParsedPoll, RPError> {
-    let poll = find_poll_desc(name)?;
+pub fn get_poll_desc(storage: &dyn Storage, name: &str, skip_summary: bool) -> Result<ParsedPoll, RPError> {
+    let poll = find_poll_desc(storage, name)?;
     // Copy all fields here
-    let mut output = ParsedPoll::new(&poll, skip_summary);
+    let mut output = ParsedPoll::new(storage, &poll, skip_summary);
 
     for entry in poll.choices {
         let path = poll.filepath.clone();
-        output.choices.push(ParsedChoice::new(entry, Path::new(&path), skip_summary)?);
+        output.choices.push(ParsedChoice::new(storage, entry, Path::new(&path), skip_summary)?);
     }
     return Ok(output);
 }
 
-pub fn get_poll_list() -> Result<Vec<Poll>, serde_yaml::Error> {
-    let polls = glob("./polls/*.yml").expect("Failed to read glob pattern");
+pub fn get_poll_list(storage: &dyn Storage) -> Result<Vec<Poll>, RPError> {
+    Ok(with_desc(storage, storage.get_poll_list()?))
+}
+
+// Render the description of the polls, the ones failing are skipped
+fn with_desc(storage: &dyn Storage, polls: Vec<Poll>) -> Vec<Poll> {
     let mut output = Vec::new();
-    for entry in polls {
-        match entry {
-            Ok(path) => { 
-                println!("Found: {:?}", path.display()); 
-                match parse_poll_file(&path) {
-                    Ok(poll) => output.push(poll),
-                    Err(e) => println!("Failed parsing {:?} with error {:?}", path.display(), e),
-                }  
-            },
-            Err(e) => println!("Failed with error: {:?}", e),
+    for poll in polls {
+        let filepath = poll.filepath.clone();
+        match fill_desc(storage, poll) {
+            Ok(poll) => output.push(poll),
+            Err(e) => println!("Failed parsing {:?} with error {:?}", filepath, e),
         }
     }
-    return Ok(output);
+    output
 }
 
-pub fn get_poll_desc_list(voter: &String) -> Result<Vec<PollDesc>, serde_yaml::Error> {
-    let polls = get_poll_list()?;
+pub fn get_poll_desc_list(storage: &dyn Storage, voter: &String) -> Result<Vec<PollDesc>, RPError> {
+    let polls = with_desc(storage, storage.get_voter_poll_list(voter)?);
     let mut output = Vec::new();
     for poll in polls {
         if !poll.allowed_participant.contains(voter) {
             continue;
         }
+        let complete = poll.is_complete();
         let filepath = poll.filename.unwrap_or("".to_string());
         let close_date = poll.deadline_date.signed_duration_since(Utc::now()) < chrono::Duration::days(1);
         let done = poll.deadline_date.signed_duration_since(Utc::now()) < chrono::Duration::seconds(1);
         let opt = poll.options.unwrap_or_default();
-        let complete = {
-            let voters = poll.allowed_participant.len();
-            let mut ret = true; 
-            for choice in poll.choices {
-                if choice.vote.len() < voters {
-                    ret = false;
-                    break;
-                }
-            }
-            ret
-        };
         output.push(PollDesc { name: poll.name.clone(), desc: poll.desc.clone(), filepath: filepath, deadline_date: format!("{}", poll.deadline_date.format(DEADLINE_FORMAT)), deadline_near: close_date, deadline_passed: done, options: opt, complete: complete });
     }
     return Ok(output);
 }
 
-pub fn compute_poll_result(poll: &Poll) -> Result<PollResult, RPError> {
-    return Ok(PollResult::new(&poll));
+pub fn compute_poll_result(storage: &dyn Storage, poll: &Poll) -> Result<PollResult, RPError> {
+    return Ok(PollResult::new(&poll, poll.voting_algorithm, &voters::get_voter_weights(storage)));
+}
+
+// Run all the algorithms using the poll's kind of ballot (score or Yes/No) over the same ballots
+pub fn compare_poll_results(storage: &dyn Storage, poll: &Poll) -> PollComparison {
+    let weights = voters::get_voter_weights(storage);
+    let reference = PollResult::new(&poll, poll.voting_algorithm, &weights);
+    let reference_winners = reference.winners();
+    let mut results = Vec::new();
+    for algorithm in VotingAlgorithm::all() {
+        if algorithm.binary_ballot() != poll.voting_algorithm.binary_ballot() {
+            continue;
+        }
+        let result = PollResult::new(&poll, algorithm, &weights);
+        // A failed result has no vote and the reason in its description
+        let error = if result.votes.is_empty() && !poll.choices.is_empty() { Some(result.desc.clone()) } else { None };
+        let winners = result.winners();
+        results.push(AlgorithmResult {
+            algorithm: algorithm,
+            name: format!("{:?}", algorithm),
+            agree: error.is_none() && winners == reference_winners,
+            winners: winners,
+            ranking: result.votes,
+            score: result.score,
+            error: error,
+        });
+    }
+    PollComparison {
+        name: poll.name.clone(),
+        desc: poll.desc.clone(),
+        user: "".to_string(),
+        algorithm: format!("{:?}", poll.voting_algorithm),
+        unanimous: results.iter().all(|x| x.agree),
+        results: results,
+    }
 }
 
-pub fn get_poll_result(name: &str, voter_name: String) -> Result<PollResult, RPError> {
-    let poll = find_poll_desc(name)?;
+pub fn get_poll_comparison(storage: &dyn Storage, name: &str, voter_name: String) -> Result<PollComparison, RPError> {
+    let poll = find_poll_desc(storage, name)?;
     if !poll.allowed_participant.contains(&voter_name) {
         return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("{} not allowed", voter_name))));
     }
 
-    return compute_poll_result(&poll);
+    return Ok(compare_poll_results(storage, &poll));
 }
 
-pub fn vote_for_poll(name: &str,  voters: &VotesForVoter) -> Result<PollResult, RPError> {
-    let mut poll = find_poll_desc(name)?;
-    if !poll.allowed_participant.contains(&voters.username) {
-        return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("{} not allowed", voters.username))));
-    }
-    // Can we still accept this vote ?
-    let late_vote = match &poll.options { Some(o) => o.allow_late_vote, None => false };
-    if !late_vote && poll.deadline_date.signed_duration_since(Utc::now()) < chrono::Duration::seconds(1) {
-        return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("{} deadline passed", name))));
+pub fn get_poll_result(storage: &dyn Storage, name: &str, voter_name: String) -> Result<PollResult, RPError> {
+    let poll = find_poll_desc(storage, name)?;
+    if !poll.allowed_participant.contains(&voter_name) {
+        return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("{} not allowed", voter_name))));
     }
-    let missing_choice = match &poll.options { Some(o) => o.allow_missing_choice, None => false };
 
+    return compute_poll_result(storage, &poll);
+}
 
-    for choice in &mut poll.choices {
-        let index = choice.voter.iter().position(|r| r == &voters.username);
-        // Check if we have a vote for this choice
-        let vote = voters.votes.get(&choice.name);
-        if vote == None {
-            if missing_choice {
-                // No, we don't, let's skip this solution
-                continue; 
+// Check a ballot against the poll's rules before accepting it
+pub fn validate_ballot(poll: &Poll, votes: &HashMap<String, i32>) -> Result<(), BallotError> {
+    // Sort the ballot so the reported error doesn't depend on the hash map order
+    let mut ballot = Vec::from_iter(votes.iter());
+    ballot.sort();
+    for (choice, _) in ballot.iter() {
+        if !poll.choices.iter().any(|c| &&c.name == choice) {
+            return Err(BallotError::UnknownChoice(choice.to_string()));
+        }
+    }
+    let missing_choice = match &poll.options { Some(o) => o.allow_missing_choice, None => false };
+    if !missing_choice {
+        if let Some(choice) = poll.choices.iter().find(|c| !votes.contains_key(&c.name)) {
+            return Err(BallotError::MissingChoice(choice.name.clone()));
+        }
+    }
+    let range = poll.score_range();
+    for &(choice, &vote) in ballot.iter() {
+        if !range.contains(vote) {
+            if poll.voting_algorithm.binary_ballot() {
+                return Err(BallotError::NotBinary { choice: choice.clone(), vote: vote });
+            }
+            return Err(BallotError::OutOfRange { choice: choice.clone(), vote: vote, min: range.min, max: range.max });
+        }
+    }
+    if poll.voting_algorithm.binary_ballot() {
+        let approvals = votes.values().filter(|&&x| x == range.max).count();
+        if let Some(max) = poll.options.as_ref().and_then(|o| o.max_approvals) {
+            if approvals > max {
+                return Err(BallotError::TooManyApprovals { count: approvals, max: max });
             }
-            else {
-                return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} invalid vote", name))));
+        }
+        if let Some(min) = poll.options.as_ref().and_then(|o| o.min_approvals) {
+            if approvals < min {
+                return Err(BallotError::TooFewApprovals { count: approvals, min: min });
             }
         }
-        match index {
-            Some(n) => choice.vote[n] = *vote.unwrap() as usize,
-            None => { 
-                choice.voter.push(voters.username.clone());
-                choice.vote.push(*vote.unwrap() as usize);
+    }
+    if !poll.voting_algorithm.allow_same_vote() {
+        let mut seen: HashMap<i32, &String> = HashMap::new();
+        for &(choice, &vote) in ballot.iter() {
+            if let Some(first) = seen.insert(vote, choice) {
+                return Err(BallotError::SameVote { first: first.clone(), second: choice.clone(), vote: vote });
             }
         }
     }
-    // Then serialize the poll and save to file
-    let serial = serde_yaml::to_string(&poll)?;
-    fs::write(poll.filepath.clone(), serial)?;
+    Ok(())
+}
+
+pub fn vote_for_poll(storage: &dyn Storage, name: &str,  voters: &VotesForVoter) -> Result<PollResult, RPError> {
+    // The checks are done on the stored poll, while no other change can happen to it
+    let poll = storage.save_ballot(name, &voters.username, &mut |poll| {
+        if !poll.allowed_participant.contains(&voters.username) {
+            return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("{} not allowed", voters.username))));
+        }
+        // Can we still accept this vote ?
+        let late_vote = match &poll.options { Some(o) => o.allow_late_vote, None => false };
+        if !late_vote && poll.deadline_date.signed_duration_since(Utc::now()) < chrono::Duration::seconds(1) {
+            return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("{} deadline passed", name))));
+        }
+        // Don't let an invalid ballot reach the storage
+        validate_ballot(&poll, &voters.votes)?;
+
+        for choice in &mut poll.choices {
+            let index = choice.voter.iter().position(|r| r == &voters.username);
+            // Check if we have a vote for this choice
+            // A missing vote is an explicit abstention here (missing choices are allowed, else the ballot would have been rejected)
+            let vote = voters.votes.get(&choice.name).cloned();
+            match index {
+                Some(n) => choice.vote[n] = vote,
+                None => { 
+                    choice.voter.push(voters.username.clone());
+                    choice.vote.push(vote);
+                }
+            }
+        }
+        poll.record_tie_break_seed();
+        Ok(())
+    })?;
 
     // Add or update the vote for the given voter
-    return compute_poll_result(&poll);
+    return compute_poll_result(storage, &fill_desc(storage, poll)?);
 }
 
 pub fn gen_template(dest: &str) {
     let mut choices = Vec::new();
-    choices.push(Choice { name:"pear".to_string(), desc: "".to_string(), description: Some("A pear is good".to_string()), desc_markdown: None, vote: vec![3, 4], voter: vec!["John".to_string(), "Bob".to_string()] });
-    choices.push(Choice { name:"apple".to_string(), desc: "".to_string(), description: Some("An apple a day...".to_string()), desc_markdown: None, vote: vec![5, 2], voter: vec!["John".to_string(), "Bob".to_string()] });
+    choices.push(Choice { name:"pear".to_string(), desc: "".to_string(), description: Some("A pear is good".to_string()), desc_markdown: None, vote: vec![Some(3), Some(4)], voter: vec!["John".to_string(), "Bob".to_string()] });
+    choices.push(Choice { name:"apple".to_string(), desc: "".to_string(), description: Some("An apple a day...".to_string()), desc_markdown: None, vote: vec![Some(5), Some(2)], voter: vec!["John".to_string(), "Bob".to_string()] });
 
     let poll = Poll::new("Best fruit".to_string(), Some("Choose your best fruit".to_string()), Some(choices));
-    let serial = serde_yaml::to_string(&poll);
+    let serial = migration::to_yaml(&poll, migration::POLL_FORMAT_VERSION);
     match serial {
         Ok(v) => fs::write(dest, v).expect("Failed writing"),
         Err(e) => println!("Failed to generate template {:?} with error: {:?}", dest, e),
     }
 }
 
-pub fn delete_poll(filestem: &str) -> bool {
-    fs::remove_file(format!("polls/{}.yml", filestem)).is_ok() 
+pub fn delete_poll(storage: &dyn Storage, filestem: &str) -> bool {
+    storage.delete_poll(filestem).is_ok()
 }
 
-pub fn update_poll(filestem: &str, poll: &Poll) -> bool {
-    let serial = serde_yaml::to_string(&poll);
-    match serial {
-        Ok(v) => { fs::write(format!("polls/{}.yml", filestem), v).expect("Failed writing"); true },
-        Err(e) => { println!("Failed to save file {:?} with error: {:?}", filestem, e); false },
+pub fn update_poll(storage: &dyn Storage, filestem: &str, poll: &Poll) -> bool {
+    match storage.save_poll(filestem, poll) {
+        Ok(_) => true,
+        Err(e) => { println!("Failed to save poll {:?} with error: {}", filestem, e); false },
     }
 }
 
-pub fn del_voter_in_poll(filestem: &str, voter: &str) -> bool {
-    let mut poll = match find_poll_desc(filestem) {
-        Ok(v) => v,
-        Err(_) => { return false; }
-    };
-    if !poll.allowed_participant.contains(&voter.to_string()) {
-        return false;
-    }
-    // Do we have any vote already ? 
-    if poll.choices.iter().filter(|x| x.voter.len() > 0 || x.vote.len() > 0).count() > 0 {
-        // We have to rename this participant to "<deleted_voter>" so that previous vote results don't change
-        for choice in &mut poll.choices {
-            for prev_voter in choice.voter.iter_mut() {
-                if prev_voter == voter {
-                    *prev_voter = format!("<deleted_{}>", voter);
+// Save the changes made in the poll editor, while no vote can change the poll
+pub fn edit_poll(storage: &dyn Storage, filestem: &str, edited: &Poll) -> Result<Poll, RPError> {
+    storage.update_poll(filestem, &mut |poll| {
+        poll.apply_edit(edited);
+        poll.validate()
+    })
+}
+
+pub fn del_voter_in_poll(storage: &dyn Storage, filestem: &str, voter: &str) -> bool {
+    let result = storage.update_poll(filestem, &mut |poll| {
+        if !poll.allowed_participant.contains(&voter.to_string()) {
+            return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not in poll", voter))));
+        }
+        // Do we have any vote already ? 
+        if poll.choices.iter().filter(|x| x.voter.len() > 0 || x.vote.len() > 0).count() > 0 {
+            // We have to rename this participant to "<deleted_voter>" so that previous vote results don't change
+            for choice in &mut poll.choices {
+                for prev_voter in choice.voter.iter_mut() {
+                    if prev_voter == voter {
+                        *prev_voter = format!("<deleted_{}>", voter);
+                    }
                 }
             }
-        }
-    } 
-    poll.allowed_participant.retain(|x| x != voter);
-    return update_poll(filestem, &poll);
+        } 
+        poll.allowed_participant.retain(|x| x != voter);
+        Ok(())
+    });
+    match result {
+        Ok(_) => true,
+        Err(e) => { println!("Failed to remove {:?} from poll {:?} with error: {}", voter, filestem, e); false },
+    }
 }
 
-pub fn add_voter_in_poll(filestem: &str, voter: &str) -> bool {
-    let mut poll = match find_poll_desc(filestem) {
-        Ok(v) => v,
-        Err(_) => { return false; }
-    };
-    if poll.allowed_participant.contains(&voter.to_string()) {
-        return true;
+pub fn add_voter_in_poll(storage: &dyn Storage, filestem: &str, voter: &str) -> bool {
+    let result = storage.update_poll(filestem, &mut |poll| {
+        if !poll.allowed_participant.contains(&voter.to_string()) {
+            poll.allowed_participant.push(voter.to_string());
+        }
+        Ok(())
+    });
+    match result {
+        Ok(_) => true,
+        Err(e) => { println!("Failed to add {:?} to poll {:?} with error: {}", voter, filestem, e); false },
     }
-    poll.allowed_participant.push(voter.to_string());
-    return update_poll(filestem, &poll);
 }
 
 pub struct Token
@@ -694,12 +1992,9 @@ mod jwt_numeric_date {
     }
 }
 
-pub fn gen_voters_token(name: &str) -> Result<Vec<Token>, RPError> {
-    let poll = find_poll_desc(name)?;
-    let secret = match fs::read_to_string("secret.txt") {
-        Ok(v) => v,
-        Err(e) => { return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("secret.txt not found: {}", e)))); }
-    };
+pub fn gen_voters_token(storage: &dyn Storage, name: &str) -> Result<Vec<Token>, RPError> {
+    let poll = storage.find_poll(name)?;
+    let secret = storage.token_secret()?;
 
     let enc_key = EncodingKey::from_secret(secret.as_bytes());
     let mut output = Vec::new();
@@ -716,19 +2011,329 @@ pub fn gen_voters_token(name: &str) -> Result<Vec<Token>, RPError> {
     return Ok(output);
 }
 
-pub fn validate_token(token: &String) -> Result<(String, String), RPError> {
-    let secret = match fs::read_to_string("secret.txt") {
-        Ok(v) => v,
-        Err(e) => { return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("secret.txt not found: {}", e)))); }
-    };
+pub fn validate_token(storage: &dyn Storage, token: &String) -> Result<(String, String), RPError> {
+    let secret = storage.token_secret()?;
     let dec_key = &DecodingKey::from_secret(secret.as_bytes());
     let token_msg = match decode::<Claims>(token, &dec_key, &Validation::new(Algorithm::HS256)) {
         Ok(v) => v,
         Err(_) => { return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} invalid", token)))); }
     };
-    let poll = find_poll_desc(&token_msg.claims.sub)?;
+    let poll = storage.find_poll(&token_msg.claims.sub)?;
     if !poll.allowed_participant.contains(&token_msg.claims.company) {
         return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Access denied")));
     }
     return Ok((token_msg.claims.sub.clone(), token_msg.claims.company.clone()));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use crate::storage::YamlStorage;
+    use crate::config::Config;
+
+    // A data folder in the system's temporary folder, removed when dropped (even if the test panics)
+    struct TempDataDir(std::path::PathBuf);
+
+    impl TempDataDir {
+        fn new(prefix: &str) -> TempDataDir {
+            let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |x| x.as_nanos());
+            let dir = std::env::temp_dir().join(format!("{}_{}_{}", prefix, std::process::id(), nanos));
+            fs::create_dir_all(dir.join("polls")).expect("Failed creating the test folder");
+            TempDataDir(dir)
+        }
+
+        fn config(&self) -> Config {
+            let mut cfg = Config::new();
+            cfg.data_dir = Some(self.0.to_string_lossy().to_string());
+            cfg
+        }
+    }
+
+    impl Drop for TempDataDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parallel_votes_are_not_lost() {
+        let filestem = "parallel_votes_test";
+        let voters: Vec<String> = (0..20).map(|i| format!("voter{}", i)).collect();
+        let choices = ["pear", "apple", "cherry"].iter().map(|name| Choice { name: name.to_string(), desc: "".to_string(), description: Some(name.to_string()), desc_markdown: None, vote: Vec::new(), voter: Vec::new() }).collect();
+        let mut poll = Poll::new("Parallel votes".to_string(), Some("Many voters at the same time".to_string()), Some(choices));
+        poll.allowed_participant = voters.clone();
+        poll.deadline_date = Utc::now() + chrono::Duration::days(1);
+        let data_dir = TempDataDir::new("rangepoll_parallel_votes");
+        let storage = Arc::new(YamlStorage::new(&data_dir.config()));
+        assert!(update_poll(&*storage, filestem, &poll));
+
+        let handles: Vec<_> = voters.iter().enumerate().map(|(i, voter)| {
+            let ballot = VotesForVoter {
+                username: voter.clone(),
+                votes: ["pear", "apple", "cherry"].iter().enumerate().map(|(j, name)| (name.to_string(), ((i + j) % 5 + 1) as i32)).collect(),
+            };
+            let storage = storage.clone();
+            thread::spawn(move || vote_for_poll(&*storage, filestem, &ballot).is_ok())
+        }).collect();
+        let accepted: Vec<bool> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(accepted.iter().all(|&x| x));
+        let saved = storage.find_poll(filestem).unwrap();
+        for choice in &saved.choices {
+            assert_eq!(choice.voter.len(), voters.len());
+            for (i, voter) in voters.iter().enumerate() {
+                let pos = choice.voter.iter().position(|x| x == voter).unwrap();
+                let j = ["pear", "apple", "cherry"].iter().position(|x| x == &choice.name).unwrap();
+                assert_eq!(choice.vote[pos], Some(((i + j) % 5 + 1) as i32));
+            }
+        }
+    }
+
+    #[test]
+    fn poll_edit_keeps_ballots_and_seed() {
+        let data_dir = TempDataDir::new("rangepoll_poll_edit");
+        let storage = YamlStorage::new(&data_dir.config());
+        let choices = ["pear", "apple"].iter().map(|name| Choice { name: name.to_string(), desc: "".to_string(), description: None, desc_markdown: None, vote: Vec::new(), voter: Vec::new() }).collect();
+        let mut poll = Poll::new("Edited".to_string(), None, Some(choices));
+        poll.allowed_participant = vec!["John".to_string(), "Bob".to_string()];
+        poll.deadline_date = Utc::now() + chrono::Duration::days(1);
+        poll.options = Some(PollOptions { tie_break: TieBreak::Lottery, ..Default::default() });
+        assert!(update_poll(&storage, "edited", &poll));
+        // The editor was opened before this vote, so its poll has no ballot and no seed
+        let mut edited = poll.clone();
+        let ballot = VotesForVoter { username: "John".to_string(), votes: [("pear".to_string(), 4), ("apple".to_string(), 2)].iter().cloned().collect() };
+        assert!(vote_for_poll(&storage, "edited", &ballot).is_ok());
+        let seed = storage.find_poll("edited").unwrap().options.unwrap().tie_break_seed;
+        assert!(seed.is_some());
+
+        edited.name = "Renamed".to_string();
+        edited.choices[1].description = Some("An apple a day".to_string());
+        edited.choices.push(Choice { name: "cherry".to_string(), desc: "".to_string(), description: None, desc_markdown: None, vote: vec![Some(5)], voter: vec!["Bob".to_string()] });
+        assert!(edit_poll(&storage, "edited", &edited).is_ok());
+
+        let saved = storage.find_poll("edited").unwrap();
+        assert_eq!(saved.name, "Renamed");
+        assert_eq!(saved.choices[1].description, Some("An apple a day".to_string()));
+        assert_eq!(saved.choices[0].voter, vec!["John"]);
+        assert_eq!(saved.choices[0].vote, vec![Some(4)]);
+        assert_eq!(saved.choices[1].vote, vec![Some(2)]);
+        // The ballots can't be added by the editor
+        assert!(saved.choices[2].voter.is_empty());
+        assert_eq!(saved.options.unwrap().tie_break_seed, seed);
+    }
+
+    #[test]
+    fn inverted_score_range_is_rejected() {
+        let content = "name: Inverted\nallowed_participant: [John]\ndeadline_date: \"2030-01-01 00:00:00\"\nchoices: []\noptions:\n  score-range: { min: 5, max: 1 }\n";
+        assert!(parse_poll(&content.replace("min: 5, max: 1", "min: 1, max: 5"), Path::new("inverted.yml")).is_ok());
+        assert!(parse_poll(content, Path::new("inverted.yml")).is_err());
+        assert!(parse_poll(&content.replace("min: 5, max: 1", "min: -49, max: 50"), Path::new("inverted.yml")).is_ok());
+        assert!(parse_poll(&content.replace("min: 5, max: 1", "min: 0, max: 100"), Path::new("inverted.yml")).is_err());
+        assert!(parse_poll(&content.replace("min: 5, max: 1", "min: 0, max: 2000000000"), Path::new("inverted.yml")).is_err());
+        let range = ScoreRange { min: 5, max: 1 };
+        assert_eq!(range.points(3), 1);
+        assert_eq!(ScoreRange { min: 1, max: 5 }.points(9), 5);
+    }
+
+    #[test]
+    fn old_skipped_choices_become_abstentions() {
+        // Before format version 1, a voter skipping a choice had no entry in the choice
+        let content = "name: Old\nallowed_participant: [Ann, Bob]\ndeadline_date: \"2030-01-01 00:00:00\"\nchoices:\n  - { name: a, desc: '', vote: [3, 4], voter: [Ann, Bob] }\n  - { name: b, desc: '', vote: [2], voter: [Bob] }\n";
+        let poll = parse_poll(content, Path::new("old.yml")).unwrap();
+        assert_eq!(poll.choices[1].voter, vec!["Bob", "Ann"]);
+        assert_eq!(poll.choices[1].vote, vec![Some(2), None]);
+        assert_eq!(poll.choices[0].vote, vec![Some(3), Some(4)]);
+        assert!(poll.is_complete());
+    }
+
+    #[test]
+    fn inconsistent_options_are_rejected() {
+        let options = |seats, min_approvals, max_approvals| PollOptions { seats, min_approvals, max_approvals, ..Default::default() };
+        let valid = |opt| ballot_poll(VotingAlgorithm::Approval, &["a", "b", "c"], &[], opt).validate().is_ok();
+        assert!(valid(options(Some(2), Some(1), Some(3))));
+        assert!(valid(options(None, Some(2), Some(2))));
+        assert!(!valid(options(Some(0), None, None)));
+        assert!(!valid(options(None, Some(3), Some(2))));
+        assert!(!valid(options(None, None, Some(4))));
+        assert!(!valid(options(None, None, Some(0))));
+    }
+
+    #[test]
+    fn majority_judgment_same_median_is_not_a_tie() {
+        // Both choices have a median of 3 (and then 3 again), only the last majority value (4 for apple, 3 for pear) separates them
+        let choices = vec![
+            Choice { name: "pear".to_string(), desc: "".to_string(), description: None, desc_markdown: None, vote: vec![Some(3), Some(3), Some(3)], voter: vec!["a".to_string(), "b".to_string(), "c".to_string()] },
+            Choice { name: "apple".to_string(), desc: "".to_string(), description: None, desc_markdown: None, vote: vec![Some(4), Some(3), Some(3)], voter: vec!["a".to_string(), "b".to_string(), "c".to_string()] },
+        ];
+        let mut poll = Poll::new("Median".to_string(), None, Some(choices));
+        poll.allowed_participant = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        poll.voting_algorithm = VotingAlgorithm::MajorityJudgment;
+        poll.options = Some(PollOptions { tie_break: TieBreak::Tie, ..Default::default() });
+        let result = PollResult::new(&poll, poll.voting_algorithm, &HashMap::new());
+        assert_eq!(result.votes, vec!["apple", "pear"]);
+        assert!(result.tied.is_empty());
+        assert!(result.tie_break.is_none());
+        assert_eq!(result.winners(), vec!["apple"]);
+    }
+
+    // A poll where each voter (v0, v1, ...) gives a ballot with the votes for the choices in the order of the names
+    fn ballot_poll(algorithm: VotingAlgorithm, names: &[&str], ballots: &[&[i32]], options: PollOptions) -> Poll {
+        let voters: Vec<String> = (0..ballots.len()).map(|i| format!("v{}", i)).collect();
+        let choices = names.iter().enumerate().map(|(j, name)| Choice { name: name.to_string(), desc: "".to_string(), description: None, desc_markdown: None, vote: ballots.iter().map(|b| Some(b[j])).collect(), voter: voters.clone() }).collect();
+        let mut poll = Poll::new(format!("{:?}", algorithm), None, Some(choices));
+        poll.allowed_participant = voters;
+        poll.voting_algorithm = algorithm;
+        poll.options = Some(options);
+        poll
+    }
+
+    // The common example of voting_algorithm.html (Alice, Bob and Cindy voting for the best fruit)
+    fn fruit_poll(algorithm: VotingAlgorithm, options: PollOptions) -> Poll {
+        ballot_poll(algorithm, &["apple", "cherry", "pear"], &[&[2, 3, 1], &[1, 3, 2], &[4, 1, 5]], options)
+    }
+
+    // 4 voters where b and c have the same first choices and points, only a has more first choices than them
+    fn second_place_tie_poll(algorithm: VotingAlgorithm) -> Poll {
+        ballot_poll(algorithm, &["a", "b", "c", "d"], &[&[4, 2, 3, 1], &[4, 3, 2, 1], &[3, 4, 2, 1], &[3, 2, 4, 1]], Default::default())
+    }
+
+    fn result(poll: &Poll) -> PollResult {
+        PollResult::new(poll, poll.voting_algorithm, &HashMap::new())
+    }
+
+    #[test]
+    fn star_finalist_tie_is_reported() {
+        // Apple and Cherry have 7 points, Apple is kept by the declared order and loses the runoff against Pear
+        let result = result(&fruit_poll(VotingAlgorithm::Star, Default::default()));
+        assert_eq!(result.rounds[1].choices, vec!["pear", "apple"]);
+        assert_eq!(result.votes[0], "pear");
+        assert_eq!(result.tied, vec!["apple", "cherry"]);
+        assert_eq!(result.tie_break, Some("the declared order of the choices".to_string()));
+        // Such a tie can't be kept, Pear is still the only winner
+        let result = PollResult::new(&fruit_poll(VotingAlgorithm::Star, PollOptions { tie_break: TieBreak::Tie, ..Default::default() }), VotingAlgorithm::Star, &HashMap::new());
+        assert_eq!(result.tied, vec!["apple", "cherry"]);
+        assert!(result.tie_break.is_some());
+        assert_eq!(result.winners(), vec!["pear"]);
+    }
+
+    #[test]
+    fn french_system_finalist_tie_is_reported() {
+        let result = result(&second_place_tie_poll(VotingAlgorithm::FrenchSystem));
+        assert_eq!(result.rounds.len(), 2);
+        assert_eq!(result.rounds[1].choices, vec!["a", "b"]);
+        assert_eq!(result.votes[0], "a");
+        assert_eq!(result.tied, vec!["b", "c"]);
+        assert_eq!(result.tie_break, Some("the declared order of the choices".to_string()));
+    }
+
+    #[test]
+    fn successive_elimination_tie_is_reported() {
+        // d is eliminated first, then b and c are tied and c (declared last) is eliminated
+        let result = result(&second_place_tie_poll(VotingAlgorithm::SuccessiveElimination));
+        assert_eq!(result.rounds.len(), 3);
+        assert_eq!(result.rounds[0].eliminated, vec!["d"]);
+        assert_eq!(result.rounds[1].eliminated, vec!["c"]);
+        assert_eq!(result.votes, vec!["a", "b", "c", "d"]);
+        assert_eq!(result.tied, vec!["b", "c"]);
+        assert_eq!(result.tie_break, Some("the declared order of the choices".to_string()));
+    }
+
+    fn assert_scores(result: &PollResult, expected: &[f32]) {
+        assert_eq!(result.score.len(), expected.len());
+        for (score, expected) in result.score.iter().zip(expected) {
+            assert!((score - expected).abs() < 1e-4, "{:?} != {:?}", result.score, expected);
+        }
+    }
+
+    #[test]
+    fn borda_variants_follow_the_documented_example() {
+        // Alice: cherry > apple > pear, Bob: cherry > pear > apple, Cindy: pear > apple > cherry
+        let borda = result(&fruit_poll(VotingAlgorithm::Borda, Default::default()));
+        assert_eq!(borda.votes, vec!["cherry", "pear", "apple"]);
+        assert_scores(&borda, &[4.0 / 3.0, 1.0, 2.0 / 3.0]);
+        let dowdall = result(&fruit_poll(VotingAlgorithm::Dowdall, Default::default()));
+        assert_eq!(dowdall.votes, vec!["cherry", "pear", "apple"]);
+        assert_scores(&dowdall, &[7.0 / 9.0, 11.0 / 18.0, 4.0 / 9.0]);
+        let modified = result(&fruit_poll(VotingAlgorithm::ModifiedBorda, Default::default()));
+        assert_eq!(modified.votes, vec!["cherry", "pear", "apple"]);
+        assert_scores(&modified, &[7.0 / 3.0, 2.0, 5.0 / 3.0]);
+    }
+
+    #[test]
+    fn borda_tie_is_kept_or_broken() {
+        let ballots: &[&[i32]] = &[&[2, 1], &[1, 2]];
+        let tied = result(&ballot_poll(VotingAlgorithm::Borda, &["a", "b"], ballots, PollOptions { tie_break: TieBreak::Tie, ..Default::default() }));
+        assert_eq!(tied.tied, vec!["a", "b"]);
+        assert!(tied.tie_break.is_none());
+        assert_eq!(tied.winners(), vec!["a", "b"]);
+        let broken = result(&ballot_poll(VotingAlgorithm::Borda, &["a", "b"], ballots, Default::default()));
+        assert_eq!(broken.tied, vec!["a", "b"]);
+        assert_eq!(broken.tie_break, Some("the declared order of the choices".to_string()));
+        assert_eq!(broken.winners(), vec!["a"]);
+    }
+
+    #[test]
+    fn condorcet_methods_follow_the_documented_example() {
+        for algorithm in &[VotingAlgorithm::Schulze, VotingAlgorithm::RankedPairs, VotingAlgorithm::MajorityJudgment] {
+            let result = result(&fruit_poll(*algorithm, Default::default()));
+            assert_eq!(result.votes, vec!["cherry", "pear", "apple"], "{:?}", algorithm);
+            assert!(result.tied.is_empty(), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn cycle_is_resolved_by_the_strongest_preferences() {
+        // 3 voters a > b > c, 2 voters b > c > a, 2 voters c > a > b: a beats b 5-2, b beats c 5-2, c beats a 4-3
+        let ballots: &[&[i32]] = &[&[3, 2, 1], &[3, 2, 1], &[3, 2, 1], &[1, 3, 2], &[1, 3, 2], &[2, 1, 3], &[2, 1, 3]];
+        for algorithm in &[VotingAlgorithm::Schulze, VotingAlgorithm::RankedPairs] {
+            let result = result(&ballot_poll(*algorithm, &["a", "b", "c"], ballots, Default::default()));
+            assert_eq!(result.votes, vec!["a", "b", "c"], "{:?}", algorithm);
+            assert!(result.tied.is_empty(), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn star_runoff_can_overturn_the_scores() {
+        // b has the most points, but 2 voters out of 3 prefer a in the runoff
+        let result = result(&ballot_poll(VotingAlgorithm::Star, &["a", "b", "c"], &[&[5, 4, 1], &[5, 4, 1], &[1, 5, 5]], Default::default()));
+        assert_eq!(result.rounds[1].choices, vec!["b", "a"]);
+        assert_eq!(result.winners(), vec!["a"]);
+        assert!(result.tied.is_empty());
+    }
+
+    #[test]
+    fn stv_elects_several_seats() {
+        // The quota is 2 votes: cherry is elected by Alice and Bob, apple is eliminated and pear takes the last seat
+        let result = result(&fruit_poll(VotingAlgorithm::SingleTransferableVote, PollOptions { seats: Some(2), ..Default::default() }));
+        assert_eq!(result.elected, vec!["cherry", "pear"]);
+        assert_eq!(result.winners(), vec!["cherry", "pear"]);
+    }
+
+    #[test]
+    fn spav_reweights_the_ballots_of_elected_choices() {
+        // Approval would elect a and b, but the voters of a count for half when b is compared with c
+        let ballots: &[&[i32]] = &[&[1, 1, 0], &[1, 1, 0], &[1, 1, 0], &[1, 0, 0], &[0, 0, 1], &[0, 0, 1]];
+        let result = result(&ballot_poll(VotingAlgorithm::SequentialProportionalApproval, &["a", "b", "c"], ballots, PollOptions { seats: Some(2), ..Default::default() }));
+        assert_eq!(result.elected, vec!["a", "c"]);
+        assert!(result.tied.is_empty());
+    }
+
+    #[test]
+    fn schulze_path_strength_is_in_declared_order() {
+        // The fallback tie break orders the choices by their mean vote (cherry, apple, pear), the matrix must still follow the poll
+        let voters = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let choices = ["pear", "apple", "cherry"].iter().enumerate().map(|(i, name)| Choice { name: name.to_string(), desc: "".to_string(), description: None, desc_markdown: None, vote: vec![Some(i as i32 + 1); 3], voter: voters.clone() }).collect();
+        let mut poll = Poll::new("Declared order".to_string(), None, Some(choices));
+        poll.allowed_participant = voters.clone();
+        poll.voting_algorithm = VotingAlgorithm::Schulze;
+        poll.options = Some(PollOptions { tie_break: TieBreak::Fallback, tie_break_algorithm: Some(VotingAlgorithm::Max), ..Default::default() });
+        assert_eq!(poll.tie_break_order(&HashMap::new())[0], "cherry");
+        let result = PollResult::new(&poll, poll.voting_algorithm, &HashMap::new());
+        assert_eq!(result.votes[0], "cherry");
+        let path_strength = result.path_strength.unwrap();
+        assert_eq!(path_strength.choices, vec!["pear", "apple", "cherry"]);
+        assert!(path_strength.matrix[2][0] > path_strength.matrix[0][2]);
+        assert!(path_strength.matrix[1][0] > path_strength.matrix[0][1]);
+    }
+}
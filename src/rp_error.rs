@@ -6,6 +6,8 @@ use std::error;
 pub enum RPError {
     IOError(std::io::Error),
     YAMLError(serde_yaml::Error),
+    BallotError(BallotError),
+    DBError(rusqlite::Error),
 }
 impl fmt::Display for RPError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -13,10 +15,40 @@ impl fmt::Display for RPError {
             RPError::IOError(ref e) => e.fmt(f),
             // This is a wrapper, so defer to the underlying types' implementation of `fmt`.
             RPError::YAMLError(ref e) => e.fmt(f),
+            RPError::BallotError(ref e) => e.fmt(f),
+            RPError::DBError(ref e) => e.fmt(f),
         }
     }
 }
 
+// The reasons for rejecting a submitted ballot
+#[derive(Debug, PartialEq)]
+pub enum BallotError {
+    UnknownChoice(String),
+    MissingChoice(String),
+    InvalidValue { choice: String, value: String },
+    OutOfRange { choice: String, vote: i32, min: i32, max: i32 },
+    NotBinary { choice: String, vote: i32 },
+    SameVote { first: String, second: String, vote: i32 },
+    TooManyApprovals { count: usize, max: usize },
+    TooFewApprovals { count: usize, min: usize },
+}
+impl fmt::Display for BallotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BallotError::UnknownChoice(choice) => write!(f, "Unknown choice {}", choice),
+            BallotError::MissingChoice(choice) => write!(f, "Missing vote for {}", choice),
+            BallotError::InvalidValue { choice, value } => write!(f, "Invalid vote {:?} for {}, expecting a whole number", value, choice),
+            BallotError::OutOfRange { choice, vote, min, max } => write!(f, "Invalid vote {} for {}, expecting a vote from {} to {}", vote, choice, min, max),
+            BallotError::NotBinary { choice, vote } => write!(f, "Invalid vote {} for {}, expecting 0 (No) or 1 (Yes)", vote, choice),
+            BallotError::SameVote { first, second, vote } => write!(f, "Same vote {} for {} and {}, this voting algorithm requires distinct votes", vote, first, second),
+            BallotError::TooManyApprovals { count, max } => write!(f, "{} choices approved, at most {} can be approved", count, max),
+            BallotError::TooFewApprovals { count, min } => write!(f, "{} choices approved, at least {} must be approved", count, min),
+        }
+    }
+}
+impl error::Error for BallotError {}
+
 impl error::Error for RPError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
@@ -25,6 +57,8 @@ impl error::Error for RPError {
             // cast to the trait object `&error::Error`. This works because the
             // underlying type already implements the `Error` trait.
             RPError::YAMLError(ref e) => Some(e),
+            RPError::BallotError(ref e) => Some(e),
+            RPError::DBError(ref e) => Some(e),
         }
     }
 }
@@ -41,4 +75,14 @@ impl From<std::io::Error> for RPError {
     fn from(err: std::io::Error) -> RPError {
         RPError::IOError(err)
     }
-}
\ No newline at end of file
+}
+impl From<BallotError> for RPError {
+    fn from(err: BallotError) -> RPError {
+        RPError::BallotError(err)
+    }
+}
+impl From<rusqlite::Error> for RPError {
+    fn from(err: rusqlite::Error) -> RPError {
+        RPError::DBError(err)
+    }
+}
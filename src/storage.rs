@@ -0,0 +1,685 @@
+extern crate glob;
+
+use glob::glob;
+use std::fs;
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex, MutexGuard };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::SystemTime;
+use rusqlite::{ params, Connection, OptionalExtension, TransactionBehavior };
+use crate::rp_error::RPError;
+use crate::poll::{ self, Poll };
+use crate::voters::{ self, Voter };
+use crate::config;
+use crate::migration::{ self, POLL_FORMAT_VERSION, VOTER_FORMAT_VERSION };
+
+// Where the polls (with their ballots), the voters, the markdown descriptions and the token secret are stored
+pub trait Storage: Send + Sync {
+    fn get_poll_list(&self) -> Result<Vec<Poll>, RPError>;
+    // The polls the voter is allowed to vote for
+    fn get_voter_poll_list(&self, voter: &str) -> Result<Vec<Poll>, RPError> {
+        Ok(self.get_poll_list()?.into_iter().filter(|x| x.allowed_participant.iter().any(|v| v == voter)).collect())
+    }
+    // Find a poll by its file stem
+    fn find_poll(&self, name: &str) -> Result<Poll, RPError>;
+    // Create or replace a poll (and its ballots)
+    fn save_poll(&self, name: &str, poll: &Poll) -> Result<(), RPError>;
+    fn delete_poll(&self, name: &str) -> Result<(), RPError>;
+    // Read, change and save a poll, the changes to the same poll are serialized so none is lost
+    fn update_poll(&self, name: &str, change: &mut dyn FnMut(&mut Poll) -> Result<(), RPError>) -> Result<Poll, RPError>;
+    // Same as update_poll, when the change is the given voter's ballot (and the tie break seed recorded with the first vote)
+    // The storages keeping the ballots apart only write this voter's ballot
+    fn save_ballot(&self, name: &str, _voter: &str, change: &mut dyn FnMut(&mut Poll) -> Result<(), RPError>) -> Result<Poll, RPError> {
+        self.update_poll(name, change)
+    }
+
+    fn get_voter_list(&self) -> Result<Vec<Voter>, RPError>;
+    fn save_voter(&self, name: &str, voter: &Voter) -> Result<(), RPError>;
+    fn delete_voter(&self, name: &str) -> Result<(), RPError>;
+
+    // The markdown descriptions of the polls and their choices (desc_markdown)
+    fn read_markdown(&self, name: &str) -> Result<String, RPError>;
+    fn save_markdown(&self, name: &str, content: &str) -> Result<(), RPError>;
+
+    // The secret used to sign the voting tokens
+    fn token_secret(&self) -> Result<String, RPError>;
+}
+
+// Open the storage selected in the configuration
+pub fn open(cfg: &config::Config) -> Result<Box<dyn Storage>, RPError> {
+    match cfg.storage {
+        config::StorageKind::Yaml => Ok(Box::new(YamlStorage::new(cfg))),
+        config::StorageKind::Sqlite => Ok(Box::new(SqliteStorage::open(&cfg.database(), cfg.secret_file())?)),
+    }
+}
+
+// Copy all the polls (with their markdown descriptions) and voters from a storage to another one
+pub fn copy_storage(from: &dyn Storage, to: &dyn Storage) -> Result<(), RPError> {
+    for poll in from.get_poll_list()? {
+        let markdowns = poll.desc_markdown.iter().chain(poll.choices.iter().filter_map(|x| x.desc_markdown.as_ref()));
+        for name in markdowns {
+            match from.read_markdown(name) {
+                Ok(content) => to.save_markdown(name, &content)?,
+                Err(e) => println!("Failed reading {:?} with error {}", name, e),
+            }
+        }
+        to.save_poll(&poll.filestem(), &poll)?;
+    }
+    for voter in from.get_voter_list()? {
+        to.save_voter(voter.filename.as_ref().unwrap_or(&voter.username), &voter)?;
+    }
+    Ok(())
+}
+
+fn read_secret_file(path: &Path) -> Result<String, RPError> {
+    match fs::read_to_string(path) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found: {}", path.display(), e)))),
+    }
+}
+
+// The YAML files in the given folder
+pub fn yaml_files(dir: &Path) -> glob::Paths {
+    let pattern = Path::new(&glob::Pattern::escape(&dir.to_string_lossy())).join("*.yml");
+    glob(&pattern.to_string_lossy()).expect("Failed to read glob pattern")
+}
+
+fn not_found(name: &str) -> RPError {
+    RPError::from(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", name)))
+}
+
+// Counts the temporary files, so two writes in the same process never share one
+static TMP_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Write the file to a temporary file in the same folder then rename it over the file, so a crash can't leave a truncated file
+pub fn write_file_atomic(path: &Path, content: &str) -> std::io::Result<()> {
+    // The whole file name is kept (foo.yml and foo.md must not share a temporary file)
+    let file_name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    let tmp = path.with_file_name(format!("{}.{}.{}.tmp", file_name, std::process::id(), TMP_FILE_COUNT.fetch_add(1, Ordering::Relaxed)));
+    {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+// What's used to detect a file change: its modification time and size (None if the file is missing)
+type FileStamp = Option<(SystemTime, u64)>;
+
+fn file_stamp(path: &Path) -> FileStamp {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+// A parsed poll (with its rendered description) and the stamps of the files it was built from
+struct CachedPoll {
+    stamps: Vec<(PathBuf, FileStamp)>,
+    poll: Poll,
+}
+
+impl CachedPoll {
+    fn is_fresh(&self) -> bool {
+        self.stamps.iter().all(|(path, stamp)| file_stamp(path) == *stamp)
+    }
+}
+
+// The default storage: a YAML file per poll in polls/ (with the markdown files) and per voter in voters/
+// The parsed files are kept in memory until they change on disk
+pub struct YamlStorage {
+    polls_dir: PathBuf,
+    voters_dir: PathBuf,
+    secret_file: PathBuf,
+    // One lock per poll (by file stem), held from reading the poll file to writing it back
+    locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    // Same for the voters, so a voter file is only written by one request at a time
+    voter_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    // The parsed polls and voters, by file stem
+    polls: Mutex<HashMap<String, CachedPoll>>,
+    voters: Mutex<HashMap<String, (FileStamp, Voter)>>,
+}
+
+impl YamlStorage {
+    pub fn new(cfg: &config::Config) -> YamlStorage {
+        YamlStorage { polls_dir: cfg.polls_dir(), voters_dir: cfg.voters_dir(), secret_file: cfg.secret_file(), locks: Mutex::new(HashMap::new()), voter_locks: Mutex::new(HashMap::new()), polls: Mutex::new(HashMap::new()), voters: Mutex::new(HashMap::new()) }
+    }
+
+    fn cached_polls(&self) -> MutexGuard<'_, HashMap<String, CachedPoll>> {
+        self.polls.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn cached_voters(&self) -> MutexGuard<'_, HashMap<String, (FileStamp, Voter)>> {
+        self.voters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Get the poll from the cache, or parse it (and render its description) if its file or its markdown changed
+    fn load_poll(&self, name: &str, path: &Path) -> Result<Poll, RPError> {
+        if let Some(entry) = self.cached_polls().get(name) {
+            if entry.is_fresh() {
+                return Ok(entry.poll.clone());
+            }
+        }
+        // Stamp before reading, so a change while parsing is seen on the next access
+        let mut stamps = vec![(path.to_path_buf(), file_stamp(path))];
+        let poll = YamlStorage::parse_poll_file(path)?;
+        if let Some(md) = &poll.desc_markdown {
+            let md_path = self.polls_dir.join(md);
+            stamps.push((md_path.clone(), file_stamp(&md_path)));
+        }
+        let poll = poll::fill_desc(self, poll)?;
+        self.cached_polls().insert(name.to_string(), CachedPoll { stamps: stamps, poll: poll.clone() });
+        Ok(poll)
+    }
+
+    fn load_voter(&self, name: &str, path: &Path) -> Result<Voter, RPError> {
+        let stamp = file_stamp(path);
+        if let Some((cached_stamp, voter)) = self.cached_voters().get(name) {
+            if *cached_stamp == stamp {
+                return Ok(voter.clone());
+            }
+        }
+        let voter = YamlStorage::parse_voter_file(path)?;
+        self.cached_voters().insert(name.to_string(), (stamp, voter.clone()));
+        Ok(voter)
+    }
+
+    fn named_lock(locks: &Mutex<HashMap<String, Arc<Mutex<()>>>>, name: &str) -> Arc<Mutex<()>> {
+        let mut locks = locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(name.to_string()).or_insert_with(|| Arc::new(Mutex::new(()))).clone()
+    }
+
+    fn poll_lock(&self, name: &str) -> Arc<Mutex<()>> {
+        YamlStorage::named_lock(&self.locks, name)
+    }
+
+    fn voter_lock(&self, name: &str) -> Arc<Mutex<()>> {
+        YamlStorage::named_lock(&self.voter_locks, name)
+    }
+
+    // The poll file, the name can't escape the polls folder
+    fn poll_path(&self, name: &str) -> Result<PathBuf, RPError> {
+        if name.is_empty() || name.starts_with('.') || name.contains(|c| c == '/' || c == '\\') {
+            return Err(not_found(name));
+        }
+        Ok(self.polls_dir.join(format!("{}.yml", name)))
+    }
+
+    fn parse_poll_file(path: &Path) -> Result<Poll, RPError> {
+        let content = fs::read_to_string(path)?;
+        Ok(poll::parse_poll(&content, path)?)
+    }
+
+    fn parse_voter_file(path: &Path) -> Result<Voter, RPError> {
+        let content = fs::read_to_string(path)?;
+        let mut voter = voters::parse_voter(&content)?;
+        voter.filename = Some(path.file_stem().unwrap().to_str().unwrap().to_string());
+        Ok(voter)
+    }
+}
+
+impl Storage for YamlStorage {
+    fn get_poll_list(&self) -> Result<Vec<Poll>, RPError> {
+        let polls = yaml_files(&self.polls_dir);
+        let mut output = Vec::new();
+        let mut found = Vec::new();
+        for entry in polls {
+            match entry {
+                Ok(path) => {
+                    let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+                    match self.load_poll(&name, &path) {
+                        Ok(poll) => output.push(poll),
+                        Err(e) => println!("Failed parsing {:?} with error {}", path.display(), e),
+                    }
+                    found.push(name);
+                },
+                Err(e) => println!("Failed with error: {:?}", e),
+            }
+        }
+        // Forget the deleted polls
+        self.cached_polls().retain(|name, _| found.contains(name));
+        Ok(output)
+    }
+
+    fn find_poll(&self, name: &str) -> Result<Poll, RPError> {
+        let path = self.poll_path(name)?;
+        if !path.is_file() {
+            self.cached_polls().remove(name);
+            return Err(not_found(name));
+        }
+        self.load_poll(name, &path)
+    }
+
+    fn save_poll(&self, name: &str, poll: &Poll) -> Result<(), RPError> {
+        let path = self.poll_path(name)?;
+        let lock = self.poll_lock(name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.cached_polls().remove(name);
+        write_file_atomic(&path, &migration::to_yaml(poll, POLL_FORMAT_VERSION)?)?;
+        Ok(())
+    }
+
+    fn delete_poll(&self, name: &str) -> Result<(), RPError> {
+        let path = self.poll_path(name)?;
+        let lock = self.poll_lock(name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.cached_polls().remove(name);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn update_poll(&self, name: &str, change: &mut dyn FnMut(&mut Poll) -> Result<(), RPError>) -> Result<Poll, RPError> {
+        let path = self.poll_path(name)?;
+        let lock = self.poll_lock(name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if !path.is_file() {
+            return Err(not_found(name));
+        }
+        let mut poll = YamlStorage::parse_poll_file(&path)?;
+        change(&mut poll)?;
+        self.cached_polls().remove(name);
+        write_file_atomic(&path, &migration::to_yaml(&poll, POLL_FORMAT_VERSION)?)?;
+        Ok(poll)
+    }
+
+    fn get_voter_list(&self) -> Result<Vec<Voter>, RPError> {
+        let voters = yaml_files(&self.voters_dir);
+        let mut output = Vec::new();
+        let mut found = Vec::new();
+        for entry in voters {
+            match entry {
+                Ok(path) => {
+                    let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+                    match self.load_voter(&name, &path) {
+                        Ok(voter) => output.push(voter),
+                        Err(e) => println!("Failed parsing {:?} with error {}", path.display(), e),
+                    }
+                    found.push(name);
+                },
+                Err(e) => println!("Failed with error: {:?}", e),
+            }
+        }
+        // Forget the deleted voters
+        self.cached_voters().retain(|name, _| found.contains(name));
+        Ok(output)
+    }
+
+    fn save_voter(&self, name: &str, voter: &Voter) -> Result<(), RPError> {
+        let lock = self.voter_lock(name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.cached_voters().remove(name);
+        write_file_atomic(&self.voters_dir.join(format!("{}.yml", name)), &migration::to_yaml(voter, VOTER_FORMAT_VERSION)?)?;
+        Ok(())
+    }
+
+    fn delete_voter(&self, name: &str) -> Result<(), RPError> {
+        let lock = self.voter_lock(name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.cached_voters().remove(name);
+        fs::remove_file(self.voters_dir.join(format!("{}.yml", name)))?;
+        Ok(())
+    }
+
+    fn read_markdown(&self, name: &str) -> Result<String, RPError> {
+        Ok(fs::read_to_string(self.polls_dir.join(name))?)
+    }
+
+    fn save_markdown(&self, name: &str, content: &str) -> Result<(), RPError> {
+        write_file_atomic(&self.polls_dir.join(name), content)?;
+        Ok(())
+    }
+
+    fn token_secret(&self) -> Result<String, RPError> {
+        read_secret_file(&self.secret_file)
+    }
+}
+
+// The polls' listing fields have their own columns (allowed_participant is a JSON array), so the lists don't need to parse every poll
+// The revision of a poll changes with the poll or its ballots, it's taken from the revisions table so it's never reused
+const SQLITE_SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS polls (name TEXT PRIMARY KEY, title TEXT NOT NULL DEFAULT '', deadline_date TEXT NOT NULL DEFAULT '',
+                                      allowed_participant TEXT NOT NULL DEFAULT '[]', format_version INTEGER NOT NULL DEFAULT 0,
+                                      revision INTEGER NOT NULL DEFAULT 0, content TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS revisions (name TEXT PRIMARY KEY, value INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS ballots (poll TEXT NOT NULL, voter TEXT NOT NULL, choice TEXT NOT NULL, vote INTEGER, PRIMARY KEY (poll, voter, choice));
+    CREATE TABLE IF NOT EXISTS voters (name TEXT PRIMARY KEY, format_version INTEGER NOT NULL DEFAULT 0, content TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS markdown (name TEXT PRIMARY KEY, content TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS secrets (name TEXT PRIMARY KEY, value TEXT NOT NULL);
+";
+
+// A SQLite database: the polls are stored as YAML (without the ballots), and the ballots have their own table so a vote only writes its rows
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+    // Used when the database doesn't contain the secret
+    secret_file: PathBuf,
+    // The parsed polls (with their rendered description) and their revision, by name
+    polls: Mutex<HashMap<String, (i64, Poll)>>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path, secret_file: PathBuf) -> Result<SqliteStorage, RPError> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(SQLITE_SCHEMA)?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        SqliteStorage::upgrade_schema(&tx)?;
+        tx.commit()?;
+        Ok(SqliteStorage { conn: Mutex::new(conn), secret_file: secret_file, polls: Mutex::new(HashMap::new()) })
+    }
+
+    fn cached_polls(&self) -> MutexGuard<'_, HashMap<String, (i64, Poll)>> {
+        self.polls.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Get the poll from the cache if its revision didn't change, else parse it (and render its description)
+    fn cached_poll(&self, name: &str, revision: i64) -> Result<Poll, RPError> {
+        if let Some((cached_revision, poll)) = self.cached_polls().get(name) {
+            if *cached_revision == revision {
+                return Ok(poll.clone());
+            }
+        }
+        let (poll, revision) = {
+            let conn = self.connection();
+            (SqliteStorage::load_poll(&conn, name)?, SqliteStorage::revision(&conn, name)?)
+        };
+        // The connection is released first, the markdown description is read from the database
+        let poll = poll::fill_desc(self, poll)?;
+        self.cached_polls().insert(name.to_string(), (revision, poll.clone()));
+        Ok(poll)
+    }
+
+    // The polls matching the query (returning the names and revisions), the ones failing are skipped
+    // If all the polls are listed, the deleted ones are removed from the cache
+    fn cached_poll_list(&self, query: &str, args: &[&dyn rusqlite::ToSql], all_polls: bool) -> Result<Vec<Poll>, RPError> {
+        let rows = {
+            let conn = self.connection();
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt.query_map(args, |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+            rows.collect::<Result<Vec<(String, i64)>, _>>()?
+        };
+        let mut output = Vec::new();
+        for (name, revision) in rows.iter() {
+            match self.cached_poll(name, *revision) {
+                Ok(poll) => output.push(poll),
+                Err(e) => println!("Failed parsing poll {:?} with error {}", name, e),
+            }
+        }
+        if all_polls {
+            self.cached_polls().retain(|name, _| rows.iter().any(|x| &x.0 == name));
+        }
+        Ok(output)
+    }
+
+    fn revision(conn: &Connection, name: &str) -> Result<i64, RPError> {
+        match conn.query_row("SELECT revision FROM polls WHERE name = ?1", params![name], |row| row.get(0)).optional()? {
+            Some(v) => Ok(v),
+            None => Err(not_found(name)),
+        }
+    }
+
+    // A new revision, for a changed poll
+    fn next_revision(conn: &Connection) -> Result<i64, RPError> {
+        conn.execute("INSERT INTO revisions (name, value) VALUES ('polls', 1) ON CONFLICT (name) DO UPDATE SET value = value + 1", params![])?;
+        Ok(conn.query_row("SELECT value FROM revisions WHERE name = 'polls'", params![], |row| row.get(0))?)
+    }
+
+    fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, RPError> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let columns = stmt.query_map(params![], |row| row.get::<_, String>(1))?.collect::<Result<Vec<String>, _>>()?;
+        Ok(columns.iter().any(|x| x == column))
+    }
+
+    // Add the columns missing in a database created by an older version, they're filled from the stored YAML
+    fn upgrade_schema(conn: &Connection) -> Result<(), RPError> {
+        if !SqliteStorage::has_column(conn, "polls", "format_version")? {
+            conn.execute_batch("ALTER TABLE polls ADD COLUMN title TEXT NOT NULL DEFAULT '';
+                                ALTER TABLE polls ADD COLUMN deadline_date TEXT NOT NULL DEFAULT '';
+                                ALTER TABLE polls ADD COLUMN allowed_participant TEXT NOT NULL DEFAULT '[]';
+                                ALTER TABLE polls ADD COLUMN format_version INTEGER NOT NULL DEFAULT 0;")?;
+            let mut stmt = conn.prepare("SELECT name, content FROM polls")?;
+            let rows = stmt.query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?.collect::<Result<Vec<(String, String)>, _>>()?;
+            for (name, content) in rows {
+                match poll::parse_poll(&content, Path::new(&name)) {
+                    // The content isn't rewritten, so its version stays the stored one until it's migrated
+                    Ok(poll) => SqliteStorage::write_listing(conn, &name, &poll, migration::format_version(&content))?,
+                    Err(e) => println!("Failed parsing poll {:?} with error {}", name, e),
+                }
+            }
+        }
+        if !SqliteStorage::has_column(conn, "polls", "revision")? {
+            conn.execute_batch("ALTER TABLE polls ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;")?;
+        }
+        if !SqliteStorage::has_column(conn, "voters", "format_version")? {
+            conn.execute_batch("ALTER TABLE voters ADD COLUMN format_version INTEGER NOT NULL DEFAULT 0;")?;
+            let mut stmt = conn.prepare("SELECT name, content FROM voters")?;
+            let rows = stmt.query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?.collect::<Result<Vec<(String, String)>, _>>()?;
+            for (name, content) in rows {
+                conn.execute("UPDATE voters SET format_version = ?2 WHERE name = ?1", params![name, migration::format_version(&content)])?;
+            }
+        }
+        Ok(())
+    }
+
+    // Upgrade the polls and voters stored in an older format, return the number of upgraded polls and voters
+    pub fn migrate(&self) -> Result<usize, RPError> {
+        let mut conn = self.connection();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut count = 0;
+        let polls = {
+            let mut stmt = tx.prepare("SELECT name, content FROM polls WHERE format_version < ?1")?;
+            let rows = stmt.query_map(params![POLL_FORMAT_VERSION], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<Result<Vec<(String, String)>, _>>()?
+        };
+        for (name, content) in polls {
+            match poll::parse_poll(&content, Path::new(&name)) {
+                Ok(poll) => { SqliteStorage::store_definition(&tx, &name, &poll)?; println!("Migrated poll: {:?}", name); count += 1; },
+                Err(e) => println!("Failed migrating poll {:?} with error {}", name, e),
+            }
+        }
+        let voters = {
+            let mut stmt = tx.prepare("SELECT name, content FROM voters WHERE format_version < ?1")?;
+            let rows = stmt.query_map(params![VOTER_FORMAT_VERSION], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<Result<Vec<(String, String)>, _>>()?
+        };
+        for (name, content) in voters {
+            match voters::parse_voter(&content) {
+                Ok(voter) => { SqliteStorage::store_voter(&tx, &name, &voter)?; println!("Migrated voter: {:?}", name); count += 1; },
+                Err(e) => println!("Failed migrating voter {:?} with error {}", name, e),
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Read a poll and its ballots, in the order they were cast
+    fn load_poll(conn: &Connection, name: &str) -> Result<Poll, RPError> {
+        let content: String = match conn.query_row("SELECT content FROM polls WHERE name = ?1", params![name], |row| row.get(0)).optional()? {
+            Some(v) => v,
+            None => { return Err(not_found(name)); }
+        };
+        let mut poll = poll::parse_poll(&content, Path::new(name))?;
+        let mut stmt = conn.prepare("SELECT voter, choice, vote FROM ballots WHERE poll = ?1 ORDER BY rowid")?;
+        let ballots = stmt.query_map(params![name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<i32>>(2)?)))?;
+        for ballot in ballots {
+            let (voter, choice, vote) = ballot?;
+            if let Some(c) = poll.choices.iter_mut().find(|x| x.name == choice) {
+                c.voter.push(voter);
+                c.vote.push(vote);
+            }
+        }
+        Ok(poll)
+    }
+
+    // The poll without its ballots, as stored in the polls table
+    fn definition(poll: &Poll) -> Poll {
+        let mut definition = poll.clone();
+        for choice in &mut definition.choices {
+            choice.voter.clear();
+            choice.vote.clear();
+        }
+        definition
+    }
+
+    // Update the listing columns of a poll, from its (parsed) content
+    fn write_listing(conn: &Connection, name: &str, poll: &Poll, format_version: u32) -> Result<(), RPError> {
+        conn.execute("UPDATE polls SET title = ?2, deadline_date = ?3, allowed_participant = ?4, format_version = ?5 WHERE name = ?1",
+                     params![name, poll.name, poll.deadline_date.to_rfc3339(), serde_json::to_string(&poll.allowed_participant).unwrap_or("[]".to_string()), format_version])?;
+        Ok(())
+    }
+
+    // Write a poll without its ballots, in the current format
+    fn store_definition(conn: &Connection, name: &str, poll: &Poll) -> Result<(), RPError> {
+        conn.execute("INSERT OR REPLACE INTO polls (name, revision, content) VALUES (?1, ?2, ?3)",
+                     params![name, SqliteStorage::next_revision(conn)?, migration::to_yaml(&SqliteStorage::definition(poll), POLL_FORMAT_VERSION)?])?;
+        SqliteStorage::write_listing(conn, name, poll, POLL_FORMAT_VERSION)
+    }
+
+    fn store_voter(conn: &Connection, name: &str, voter: &Voter) -> Result<(), RPError> {
+        conn.execute("INSERT OR REPLACE INTO voters (name, format_version, content) VALUES (?1, ?2, ?3)", params![name, VOTER_FORMAT_VERSION, migration::to_yaml(voter, VOTER_FORMAT_VERSION)?])?;
+        Ok(())
+    }
+
+    // Write a poll and replace its ballots
+    fn store_poll(conn: &Connection, name: &str, poll: &Poll) -> Result<(), RPError> {
+        SqliteStorage::store_definition(conn, name, poll)?;
+        conn.execute("DELETE FROM ballots WHERE poll = ?1", params![name])?;
+        let mut stmt = conn.prepare("INSERT OR REPLACE INTO ballots (poll, voter, choice, vote) VALUES (?1, ?2, ?3, ?4)")?;
+        for choice in &poll.choices {
+            for (voter, vote) in choice.voter.iter().zip(choice.vote.iter()) {
+                stmt.execute(params![name, voter, choice.name, vote])?;
+            }
+        }
+        Ok(())
+    }
+
+    // Write the voter's ballot only, an updated vote keeps its row (and so its place in the order the ballots were cast)
+    fn store_ballot(conn: &Connection, name: &str, voter: &str, poll: &Poll) -> Result<(), RPError> {
+        let mut stmt = conn.prepare("INSERT INTO ballots (poll, voter, choice, vote) VALUES (?1, ?2, ?3, ?4) ON CONFLICT (poll, voter, choice) DO UPDATE SET vote = excluded.vote")?;
+        for choice in &poll.choices {
+            if let Some(n) = choice.voter.iter().position(|x| x == voter) {
+                stmt.execute(params![name, voter, choice.name, choice.vote[n]])?;
+            }
+        }
+        conn.execute("UPDATE polls SET revision = ?2 WHERE name = ?1", params![name, SqliteStorage::next_revision(conn)?])?;
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get_poll_list(&self) -> Result<Vec<Poll>, RPError> {
+        self.cached_poll_list("SELECT name, revision FROM polls ORDER BY name", params![], true)
+    }
+
+    // Only the polls listing the voter in their allowed_participant column are read
+    fn get_voter_poll_list(&self, voter: &str) -> Result<Vec<Poll>, RPError> {
+        self.cached_poll_list("SELECT name, revision FROM polls WHERE EXISTS (SELECT 1 FROM json_each(polls.allowed_participant) WHERE json_each.value = ?1) ORDER BY name", params![voter], false)
+    }
+
+    fn find_poll(&self, name: &str) -> Result<Poll, RPError> {
+        let revision = SqliteStorage::revision(&self.connection(), name);
+        match revision {
+            Ok(v) => self.cached_poll(name, v),
+            Err(e) => { self.cached_polls().remove(name); Err(e) },
+        }
+    }
+
+    fn save_poll(&self, name: &str, poll: &Poll) -> Result<(), RPError> {
+        let mut conn = self.connection();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        SqliteStorage::store_poll(&tx, name, poll)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_poll(&self, name: &str) -> Result<(), RPError> {
+        let mut conn = self.connection();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if tx.execute("DELETE FROM polls WHERE name = ?1", params![name])? == 0 {
+            return Err(not_found(name));
+        }
+        tx.execute("DELETE FROM ballots WHERE poll = ?1", params![name])?;
+        tx.commit()?;
+        self.cached_polls().remove(name);
+        Ok(())
+    }
+
+    fn update_poll(&self, name: &str, change: &mut dyn FnMut(&mut Poll) -> Result<(), RPError>) -> Result<Poll, RPError> {
+        let mut conn = self.connection();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut poll = SqliteStorage::load_poll(&tx, name)?;
+        change(&mut poll)?;
+        SqliteStorage::store_poll(&tx, name, &poll)?;
+        tx.commit()?;
+        Ok(poll)
+    }
+
+    fn save_ballot(&self, name: &str, voter: &str, change: &mut dyn FnMut(&mut Poll) -> Result<(), RPError>) -> Result<Poll, RPError> {
+        let mut conn = self.connection();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut poll = SqliteStorage::load_poll(&tx, name)?;
+        let definition = SqliteStorage::definition(&poll);
+        change(&mut poll)?;
+        // The poll itself is only rewritten if the change touched more than the ballots (the first vote records the lottery seed)
+        let changed_definition = SqliteStorage::definition(&poll);
+        if changed_definition != definition {
+            SqliteStorage::store_definition(&tx, name, &changed_definition)?;
+        }
+        SqliteStorage::store_ballot(&tx, name, voter, &poll)?;
+        tx.commit()?;
+        Ok(poll)
+    }
+
+    fn get_voter_list(&self) -> Result<Vec<Voter>, RPError> {
+        let conn = self.connection();
+        let mut stmt = conn.prepare("SELECT name, content FROM voters ORDER BY name")?;
+        let rows = stmt.query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?.collect::<Result<Vec<(String, String)>, _>>()?;
+        let mut output = Vec::new();
+        for (name, content) in rows {
+            match voters::parse_voter(&content) {
+                Ok(mut voter) => { voter.filename = Some(name); output.push(voter); },
+                Err(e) => println!("Failed parsing voter {:?} with error {}", name, e),
+            }
+        }
+        Ok(output)
+    }
+
+    fn save_voter(&self, name: &str, voter: &Voter) -> Result<(), RPError> {
+        SqliteStorage::store_voter(&self.connection(), name, voter)
+    }
+
+    fn delete_voter(&self, name: &str) -> Result<(), RPError> {
+        if self.connection().execute("DELETE FROM voters WHERE name = ?1", params![name])? == 0 {
+            return Err(not_found(name));
+        }
+        Ok(())
+    }
+
+    fn read_markdown(&self, name: &str) -> Result<String, RPError> {
+        match self.connection().query_row("SELECT content FROM markdown WHERE name = ?1", params![name], |row| row.get(0)).optional()? {
+            Some(v) => Ok(v),
+            None => Err(not_found(name)),
+        }
+    }
+
+    fn save_markdown(&self, name: &str, content: &str) -> Result<(), RPError> {
+        let mut conn = self.connection();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute("INSERT OR REPLACE INTO markdown (name, content) VALUES (?1, ?2)", params![name, content])?;
+        // The cached polls can contain the rendered markdown, they're all read again (the descriptions rarely change)
+        tx.execute("UPDATE polls SET revision = ?1", params![SqliteStorage::next_revision(&tx)?])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // The secret is stored in the database (secrets table, "token" row), else it's read from the secret file
+    fn token_secret(&self) -> Result<String, RPError> {
+        match self.connection().query_row("SELECT value FROM secrets WHERE name = 'token'", params![], |row| row.get(0)).optional()? {
+            Some(v) => Ok(v),
+            None => read_secret_file(&self.secret_file),
+        }
+    }
+}
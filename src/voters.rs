@@ -1,11 +1,14 @@
-extern crate glob; 
-
-use glob::glob;
 use std::fs;
-use std::path::Path;
+use std::collections::HashMap;
+use crate::rp_error::RPError;
+use crate::storage::Storage;
+use crate::migration;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Voter {
+    // The version of the file's format, older files are upgraded when loaded (see migration.rs)
+    #[serde(default)]
+    pub format_version: u32,
     pub username: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
@@ -15,59 +18,68 @@ pub struct Voter {
     pub password: String,
     
     pub admin: bool,
+    // The weight of the voter's ballots (default to 1), for voters with different stakes
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub weight: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub filename: Option<String>,
 }
 
-pub fn parse_voter_file(path: &Path) -> Result<Voter, serde_yaml::Error> {
-    let content = fs::read_to_string(path).expect("<FailureToReadFile>");
-    let mut voter: Voter = serde_yaml::from_str(&content)?;
-    voter.filename = Some(path.file_stem().unwrap().to_str().unwrap().to_string());
-    return Ok(voter);
-}
-
-pub fn get_voter_list() -> Result<Vec<Voter>, serde_yaml::Error> {
-    let voters = glob("./voters/*.yml").expect("Failed to read glob pattern");
-    let mut output = Vec::new();
-    for entry in voters {
-        match entry {
-            Ok(path) => { 
-                println!("Found: {:?}", path.display()); 
-                match parse_voter_file(&path) {
-                    Ok(voter) => output.push(voter),
-                    Err(e) => println!("Failed parsing {:?} with error {:?}", path.display(), e),
-                }  
-            },
-            Err(e) => println!("Failed with error: {:?}", e),
+impl Voter {
+    // Check the voter's settings are consistent (the deserialization accepts a negative, zero or NaN weight)
+    pub fn validate(&self) -> Result<(), RPError> {
+        if let Some(weight) = self.weight {
+            if !weight.is_finite() || weight <= 0.0 {
+                return Err(RPError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid weight for {}: {} (it must be a positive number)", self.username, weight))));
+            }
         }
+        Ok(())
     }
-    return Ok(output);
 }
 
-pub fn delete_voter(filestem: &str) -> bool {
-    fs::remove_file(format!("voters/{}.yml", filestem)).is_ok() 
+// Parse a voter file, upgrading it to the current format
+pub fn parse_voter(content: &str) -> Result<Voter, RPError> {
+    let mut value: serde_yaml::Value = serde_yaml::from_str(content)?;
+    migration::migrate_voter(&mut value)?;
+    let voter: Voter = serde_yaml::from_value(value)?;
+    voter.validate()?;
+    Ok(voter)
 }
 
-pub fn update_voter(filestem: &str, voter: &Voter) -> bool {
-    let serial = serde_yaml::to_string(&voter);
-    match serial {
-        Ok(v) => { fs::write(format!("voters/{}.yml", filestem), v).expect("Failed writing"); true },
-        Err(e) => { println!("Failed to save file {:?} with error: {:?}", filestem, e); false },
+pub fn get_voter_list(storage: &dyn Storage) -> Result<Vec<Voter>, RPError> {
+    storage.get_voter_list()
+}
+
+// The weight of each voter with a weight in its voter file
+pub fn get_voter_weights(storage: &dyn Storage) -> HashMap<String, f32> {
+    get_voter_list(storage).unwrap_or_default().into_iter().filter_map(|x| x.weight.map(|w| (x.username, w))).collect()
+}
+
+pub fn delete_voter(storage: &dyn Storage, filestem: &str) -> bool {
+    storage.delete_voter(filestem).is_ok() 
+}
+
+pub fn update_voter(storage: &dyn Storage, filestem: &str, voter: &Voter) -> bool {
+    match storage.save_voter(filestem, voter) {
+        Ok(_) => true,
+        Err(e) => { println!("Failed to save voter {:?} with error: {}", filestem, e); false },
     }
 }
 
 
-pub fn gen_template(dest: &str) {
+pub fn gen_template(storage: &dyn Storage, dest: &str) {
     let voter = Voter { 
+                        format_version: migration::VOTER_FORMAT_VERSION,
                         username: "Isaac".to_string(), 
                         presentation: "I'm one of the best physician".to_string(), 
                         fullname: Some("Isaac Newton".to_string()),
                         email: Some("notinventedyet@newton.co.uk".to_string()),
                         password: "This is a very poor designed system".to_string(),
-                        admin: glob("./voters/*.yml").expect("Failed to read glob pattern").count() == 0,
+                        admin: get_voter_list(storage).map_or(true, |x| x.is_empty()),
+                        weight: None,
                         filename: None,
                     };
-    let serial = serde_yaml::to_string(&voter);
+    let serial = migration::to_yaml(&voter, migration::VOTER_FORMAT_VERSION);
     match serial {
         Ok(v) => fs::write(dest, v).expect("Failed writing"),
         Err(e) => println!("Failed to generate template {:?} with error: {:?}", dest, e),
@@ -5,6 +5,8 @@ function showMenu(html) {
 
 var votingAlgorithm = "max";
 var noChoice = false;
+var sameVote = true;
+var scoreValues = 5;
 function updateDialog(d) {
     if ($('.user.h').a.length && getCookie("user")) ajax("/user", showMenu, ignore, {});
     $('div.dialog').html(d);
@@ -16,8 +18,10 @@ function updateDialog(d) {
     if (voteEl.a.length) {
       // Change the behavior here
       votingAlgorithm = voteEl.attr("data-algorithm");
-      noChoice = voteEl.attr("data-nochoice") == "true" || votingAlgorithm == "binary";
-      $('.downHeader2 button').a[0].disabled = (!noChoice && hasNonVotedItem());
+      noChoice = voteEl.attr("data-nochoice") == "true" || voteEl.attr("data-binary") == "true";
+      sameVote = voteEl.attr("data-samevote") == "true";
+      scoreValues = voteEl.attr("data-values")|0;
+      $('.downHeader2 button').a[0].disabled = (!noChoice && hasNonVotedItem()) || !checkApprovals();
     }
 }
 
@@ -38,19 +42,33 @@ function hasSameVoteValue() {
   var inputs = $('.rating input');
   if (!inputs.a.length) return true;
   var firstName = '';
-  var obj = Array(/*$('.voteList').a.length*/5).fill(''); // The current vote 
+  var obj = {}; // The choice for each vote value
   for (var i = 0; i < inputs.a.length; i++)
   {
     if (inputs.a[i].name == firstName) continue; // Skip processed elements already
     firstName = inputs.a[i].name;
-    var val = $(`.rating input[name='${firstName}']:checked`).value()|0;
-    if (!val) continue;
-    if (obj[val-1] != '') return obj[val-1];
-    obj[val-1] = firstName;
+    var checked = $(`.rating input[name='${firstName}']:checked`);
+    if (!checked.a.length) continue;
+    var val = checked.value()|0;
+    if (obj[val] !== undefined) return obj[val];
+    obj[val] = firstName;
   }
   return '';
 }
 
+// Update the counter of remaining approvals and check the ballot is within the poll's limits
+function checkApprovals() {
+  var counter = $('#approvals');
+  if (!counter.a.length) return true;
+  var max = counter.attr("data-max")|0, min = counter.attr("data-min")|0;
+  var count = $(".binary input[value='1']:checked").a.length;
+  var text = [];
+  if (max) text.push(count <= max ? `${max - count} approval${max - count == 1 ? '' : 's'} left` : `Too many approvals: at most ${max} choices can be approved`);
+  if (min && count < min) text.push(`Approve at least ${min - count} more choice${min - count == 1 ? '' : 's'}`);
+  counter.html(text.join(', ')).toggleClass('error', max && count > max);
+  return (!max || count <= max) && count >= min;
+}
+
 function uponError(e) {
     console.log(e);
     $('div.dialog').html(e);
@@ -78,10 +96,10 @@ window.onload = function() {
         $('.voteList').toggleClass('notActive', true);
         $(e).toggleClass('notActive', false);
         $('.downHeader2 button').toggleClass('flash', false);
-        $('.downHeader2 button').a[0].disabled = (!noChoice && hasNonVotedItem());
+        $('.downHeader2 button').a[0].disabled = (!noChoice && hasNonVotedItem()) || !checkApprovals();
     });
     delegateEvent(dialog, 'change', '.rating input, .binary input', function(e, ev) {
-        if (votingAlgorithm != "max" && votingAlgorithm != "condorcet" && votingAlgorithm != "binary" && $('.voteList').a.length <= 5) {
+        if (!sameVote && $('.voteList').a.length <= scoreValues) {
           var dup = hasSameVoteValue();
           if (dup.length) {
             // Not allowed for this algorithm
@@ -98,7 +116,7 @@ window.onload = function() {
         var pos = $('.voteList').a.indexOf(vote.a[0]);
         voteList.eq(pos + 1).toggleClass('notActive', false);
         var button = $('.downHeader2 button');
-        button.a[0].disabled = (!noChoice && hasNonVotedItem());
+        button.a[0].disabled = (!noChoice && hasNonVotedItem()) || !checkApprovals();
 
         if (voteList.a.length == pos + 1 && !button.a.disabled) {
             $('.downHeader2 button').toggleClass('flash').a[0].scrollIntoView();